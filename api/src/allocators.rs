@@ -1,5 +1,10 @@
 extern crate alloc;
 
+use core::ops::{Deref, DerefMut};
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
 use crate::logger;
 
 
@@ -35,12 +40,78 @@ unsafe impl GlobalAlloc for LibcAllocator {
 }
 
 
+// host tests link std, which brings its own allocator
+#[cfg(not(test))]
 #[global_allocator]
 static A: LibcAllocator = LibcAllocator;
 
 
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn on_oom(layout: Layout) -> ! {
     log!(TAG, "out of memory error: {:?}", layout);
     loop {}
 }
+
+
+// - heap_caps buffers --------------------------------------------------------
+
+// Fixed size, zero-initialized buffer allocated with `heap_caps_calloc` so
+// callers can pick the memory region, e.g. external PSRAM for multi-second
+// delay lines which would never fit in internal RAM.
+//
+// See: https://docs.espressif.com/projects/esp-idf/en/latest/api-reference/system/mem_alloc.html
+
+pub struct CapsBuffer<T: Copy> {
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T: Copy> CapsBuffer<T> {
+    pub fn new(len: usize, caps: u32) -> Result<CapsBuffer<T>, EspError> {
+        let ptr = unsafe {
+            idf::heap_caps_calloc(len, core::mem::size_of::<T>(), caps) as *mut T
+        };
+        if ptr == core::ptr::null_mut() {
+            log!(TAG, "failed to allocate {} bytes with caps: {:#x}",
+                 len * core::mem::size_of::<T>(), caps);
+            return Err(idf::ESP_ERR_NO_MEM.into());
+        }
+        Ok(CapsBuffer {
+            ptr: ptr,
+            len: len,
+        })
+    }
+
+    // allocate from external PSRAM
+    pub fn external(len: usize) -> Result<CapsBuffer<T>, EspError> {
+        CapsBuffer::new(len, idf::MALLOC_CAP_SPIRAM | idf::MALLOC_CAP_8BIT)
+    }
+
+    // allocate from internal RAM
+    pub fn internal(len: usize) -> Result<CapsBuffer<T>, EspError> {
+        CapsBuffer::new(len, idf::MALLOC_CAP_INTERNAL | idf::MALLOC_CAP_8BIT)
+    }
+}
+
+impl<T: Copy> Deref for CapsBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for CapsBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> Drop for CapsBuffer<T> {
+    fn drop(&mut self) {
+        unsafe { idf::heap_caps_free(self.ptr as *mut cty::c_void) }
+    }
+}
+
+unsafe impl<T: Copy + Send> Send for CapsBuffer<T> {}
@@ -0,0 +1,274 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::audio::Buffer;
+use crate::fft::{Complex, Fft};
+use crate::math;
+use crate::resample::{self, Quality};
+use crate::wav::Wav;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MIN_PARTITION: usize = 32;
+pub const MAX_PARTITION: usize = 2048;
+
+// input frames read past `max_length` so the resampler has its full
+// reach at the point where the response is truncated
+const RESAMPLE_HEADROOM: usize = 128;
+
+
+// - convolution::Convolver ---------------------------------------------------
+
+// Zero latency convolution of a single channel with an impulse response.
+//
+// The first `partition` taps are a direct form FIR, the rest of the
+// response is split into partitions of the same size and convolved in the
+// frequency domain with overlap-save. Each block of input is transformed
+// once into a frequency domain delay line and multiplied with the spectra
+// of all the partitions, the tail of the response for a block is ready
+// just as the direct form head runs out.
+//
+// `partition` trades the cost of the head, `partition` multiplies a
+// sample, against the cost of the tail. Matching it to the audio block
+// length spreads the work evenly over the audio callbacks.
+//
+// See: Gardner, "Efficient convolution without input-output delay", 1995
+
+pub struct Convolver {
+    partition: usize,
+    length: usize,
+
+    head: Vec<f32>,    // first taps, reversed
+    history: Vec<f32>, // last `partition` inputs, doubled for contiguous reads
+    position: usize,
+
+    fft: Fft,
+    filters: Vec<Vec<Complex>>, // spectra of the tail partitions
+    spectra: Vec<Vec<Complex>>, // spectra of past input blocks
+    current: usize,
+    accumulator: Vec<Complex>,
+    frame: Vec<f32>,  // previous and current input block
+    output: Vec<f32>,
+    tail: Vec<f32>,   // tail output for the current block
+}
+
+
+impl Convolver {
+    pub fn new(ir: &[f32], partition: usize) -> Result<Convolver, EspError> {
+        if ir.is_empty() || partition < MIN_PARTITION || partition > MAX_PARTITION || partition.count_ones() != 1 {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        let mut fft = Fft::new(partition * 2)?;
+        let bins = fft.bins();
+
+        let mut head = vec![0.; partition];
+        for (y, x) in head.iter_mut().rev().zip(ir) {
+            *y = *x;
+        }
+
+        let tail = if ir.len() > partition { &ir[partition..] } else { &[] };
+        let mut frame = vec![0.; partition * 2];
+        let filters: Vec<Vec<Complex>> = tail.chunks(partition).map(|taps| {
+            for (i, y) in frame.iter_mut().enumerate() {
+                *y = if i < taps.len() { taps[i] } else { 0. };
+            }
+            let mut spectrum = vec![Complex::default(); bins];
+            fft.forward(&frame, &mut spectrum);
+            spectrum
+        }).collect();
+        for y in frame.iter_mut() {
+            *y = 0.;
+        }
+        let spectra = vec![vec![Complex::default(); bins]; filters.len()];
+
+        Ok(Convolver {
+            partition: partition,
+            length: ir.len(),
+            head: head,
+            history: vec![0.; partition * 2],
+            position: 0,
+            fft: fft,
+            filters: filters,
+            spectra: spectra,
+            current: 0,
+            accumulator: vec![Complex::default(); bins],
+            frame: frame,
+            output: vec![0.; partition * 2],
+            tail: vec![0.; partition],
+        })
+    }
+
+    pub fn partition(&self) -> usize {
+        self.partition
+    }
+
+    // length of the impulse response
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.history.iter_mut().chain(self.frame.iter_mut()).chain(self.tail.iter_mut()) {
+            *x = 0.;
+        }
+        for spectrum in self.spectra.iter_mut() {
+            for x in spectrum.iter_mut() {
+                *x = Complex::default();
+            }
+        }
+        self.position = 0;
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self, x: f32) -> f32 {
+        let partition = self.partition;
+        let position = self.position;
+        self.history[position] = x;
+        self.history[position + partition] = x;
+
+        let recent = &self.history[position + 1..position + 1 + partition];
+        let mut y = self.tail[position];
+        for (h, x) in self.head.iter().zip(recent) {
+            y += h * x;
+        }
+
+        self.position += 1;
+        if self.position == partition {
+            self.position = 0;
+            self.block();
+        }
+        y
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        for (y, x) in output.iter_mut().zip(input) {
+            *y = self.tick(*x);
+        }
+    }
+
+    // Adds the latest input block to the delay line and computes the tail
+    // output for the next block.
+    fn block(&mut self) {
+        let partition = self.partition;
+        let count = self.filters.len();
+        if count == 0 {
+            return;
+        }
+
+        self.frame.copy_within(partition.., 0);
+        self.frame[partition..].copy_from_slice(&self.history[partition..]);
+        self.current = if self.current == 0 { count - 1 } else { self.current - 1 };
+        self.fft.forward(&self.frame, &mut self.spectra[self.current]);
+
+        for x in self.accumulator.iter_mut() {
+            *x = Complex::default();
+        }
+        for (k, filter) in self.filters.iter().enumerate() {
+            let spectrum = &self.spectra[(self.current + k) % count];
+            for ((y, x), h) in self.accumulator.iter_mut().zip(spectrum).zip(filter) {
+                *y = *y + *x * *h;
+            }
+        }
+
+        // only the second half of the inverse is free of circular wrap around
+        self.fft.inverse(&self.accumulator, &mut self.output);
+        self.tail.copy_from_slice(&self.output[partition..]);
+    }
+}
+
+
+// - convolution::Convolution -------------------------------------------------
+
+// Convolution processor for cabinet and room impulse responses, with one
+// `Convolver` per channel. A mono response is used for every channel.
+
+pub struct Convolution {
+    pub gain: f32, // dB, applied to the wet signal
+    pub mix: f32,  // 0 - 1, dry / wet
+
+    convolvers: Vec<Convolver>,
+    wet: f32,
+}
+
+
+impl Convolution {
+    // `irs` holds a response for each channel, the last one is repeated
+    // for any further channels
+    pub fn new(num_channels: usize, partition: usize, irs: &[&[f32]]) -> Result<Convolution, EspError> {
+        if irs.is_empty() {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        let mut convolvers = Vec::with_capacity(num_channels);
+        for channel in 0..num_channels.max(1) {
+            convolvers.push(Convolver::new(irs[channel.min(irs.len() - 1)], partition)?);
+        }
+        Ok(Convolution {
+            gain: 0.,
+            mix: 1.,
+            convolvers: convolvers,
+            wet: 1.,
+        })
+    }
+
+    // Loads the impulse response from a WAV file, resampled to `fs` if it
+    // was recorded at a different rate. Responses longer than `max_length`
+    // seconds are truncated.
+    pub fn from_wav(fs: f32, num_channels: usize, partition: usize, wav: &Wav, max_length: f32) -> Result<Convolution, EspError> {
+        let channels = wav.spec.num_channels as usize;
+        let ratio = fs / wav.spec.fs as f32;
+        let resampled = math::fabsf(ratio - 1.) > 1e-6;
+        if resampled && (ratio < resample::MIN_RATIO || ratio > resample::MAX_RATIO) {
+            return Err(idf::ESP_ERR_NOT_SUPPORTED.into());
+        }
+
+        // only decode the part of the file that will be kept
+        let keep = (max_length * wav.spec.fs as f32) as usize;
+        let keep = if resampled { keep.saturating_add(RESAMPLE_HEADROOM) } else { keep };
+        let mut data = vec![0.; wav.frames().min(keep) * channels];
+        wav.read(0, &mut data);
+        if resampled {
+            data = resample::resample(Quality::High, channels, ratio, &data);
+        }
+
+        let frames = (data.len() / channels).min((max_length * fs) as usize);
+        let irs: Vec<Vec<f32>> = (0..channels).map(|channel| {
+            (0..frames).map(|frame| data[frame * channels + channel]).collect()
+        }).collect();
+        let irs: Vec<&[f32]> = irs.iter().map(|ir| ir.as_slice()).collect();
+        Convolution::new(num_channels, partition, &irs)
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+        self.wet = math::db_to_gain(gain);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn reset(&mut self) {
+        for convolver in self.convolvers.iter_mut() {
+            convolver.reset();
+        }
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let dry = 1. - self.mix;
+        let wet = self.mix * self.wet;
+        let count = num_channels.min(self.convolvers.len());
+        for frame in buffer.chunks_mut(num_channels) {
+            for (x, convolver) in frame[..count].iter_mut().zip(self.convolvers.iter_mut()) {
+                *x = *x * dry + convolver.tick(*x) * wet;
+            }
+        }
+    }
+}
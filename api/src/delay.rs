@@ -0,0 +1,268 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::ops::DerefMut;
+
+use esp_idf::EspError;
+
+use crate::allocators::CapsBuffer;
+use crate::audio::Buffer;
+use crate::math;
+use crate::smooth;
+
+
+// - delay::DelayLine ---------------------------------------------------------
+
+// Circular delay line over any `[f32]` backing store.
+//
+// Reads are expressed in samples relative to the next write, so reading a
+// delay of `d` before calling `write` returns the sample written `d` calls
+// ago. Valid delays are [1, len - 1], or [1, len - 3] for `read_cubic`.
+
+pub struct DelayLine<B = Vec<f32>> {
+    buffer: B,
+    write_index: usize,
+}
+
+
+impl DelayLine<Vec<f32>> {
+    pub fn new(length: usize) -> DelayLine<Vec<f32>> {
+        DelayLine::from_buffer(vec![0.; length.max(4)])
+    }
+}
+
+
+impl DelayLine<CapsBuffer<f32>> {
+    // allocate the delay line from external PSRAM
+    pub fn external(length: usize) -> Result<DelayLine<CapsBuffer<f32>>, EspError> {
+        Ok(DelayLine::from_buffer(CapsBuffer::external(length.max(4))?))
+    }
+}
+
+
+impl<B: DerefMut<Target = [f32]>> DelayLine<B> {
+    pub fn from_buffer(buffer: B) -> DelayLine<B> {
+        DelayLine {
+            buffer: buffer,
+            write_index: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn clear(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0.;
+        }
+        self.write_index = 0;
+    }
+
+    #[inline(always)]
+    pub fn write(&mut self, x: f32) {
+        self.buffer[self.write_index] = x;
+        self.write_index += 1;
+        if self.write_index == self.buffer.len() {
+            self.write_index = 0;
+        }
+    }
+
+    #[inline(always)]
+    pub fn read(&self, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        let delay = if delay < 1 { 1 } else if delay >= len { len - 1 } else { delay };
+        let index = if delay > self.write_index {
+            self.write_index + len - delay
+        } else {
+            self.write_index - delay
+        };
+        self.buffer[index]
+    }
+
+    #[inline(always)]
+    pub fn read_linear(&self, delay: f32) -> f32 {
+        let max = (self.buffer.len() - 2) as f32;
+        let delay = math::clamp(delay, 1., max);
+        let int_part = delay as usize;
+        let frac_part = delay - int_part as f32;
+        let y0 = self.read(int_part);
+        let y1 = self.read(int_part + 1);
+        y0 + (y1 - y0) * frac_part
+    }
+
+    // 4-point, 3rd-order hermite interpolation
+    #[inline(always)]
+    pub fn read_cubic(&self, delay: f32) -> f32 {
+        let max = (self.buffer.len() - 3) as f32;
+        let delay = math::clamp(delay, 1., max);
+        let int_part = delay as usize;
+        let t = delay - int_part as f32;
+        let xm1 = if int_part > 1 { self.read(int_part - 1) } else { self.read(int_part) };
+        let x0 = self.read(int_part);
+        let x1 = self.read(int_part + 1);
+        let x2 = self.read(int_part + 2);
+        let c0 = x0;
+        let c1 = 0.5 * (x1 - xm1);
+        let c2 = xm1 - 2.5 * x0 + 2. * x1 - 0.5 * x2;
+        let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+        ((c3 * t + c2) * t + c1) * t + c0
+    }
+}
+
+
+// - delay::Echo --------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    Stereo,   // independent left and right feedback loops
+    PingPong, // the feedback of each side feeds the other
+}
+
+// one-pole lowpass & dc-blocking highpass in each feedback loop
+#[derive(Copy, Clone, Debug)]
+struct LoopFilter {
+    lowpass: f32,
+    highpass_x: f32,
+    highpass_y: f32,
+}
+
+impl LoopFilter {
+    fn new() -> LoopFilter {
+        LoopFilter { lowpass: 0., highpass_x: 0., highpass_y: 0. }
+    }
+
+    #[inline(always)]
+    fn tick(&mut self, x: f32, damping: f32, highpass: f32) -> f32 {
+        self.lowpass += (x - self.lowpass) * (1. - damping);
+        let y = self.lowpass - self.highpass_x + highpass * self.highpass_y;
+        self.highpass_x = self.lowpass;
+        self.highpass_y = y;
+        y
+    }
+}
+
+// Stereo echo with fractional, smoothed delay time. Changing the time
+// while running glides the read taps like a tape delay instead of
+// clicking.
+
+pub struct Echo<B = Vec<f32>> {
+    pub fs: f32,
+    pub mode: Mode,
+    pub feedback: f32, // 0 - 1
+    pub damping: f32,  // 0 - 1, amount of high frequency loss per repeat
+    pub mix: f32,      // 0 - 1, dry / wet
+
+    time: smooth::OnePole, // samples
+    highpass: f32,
+    lines: [DelayLine<B>; 2],
+    filters: [LoopFilter; 2],
+}
+
+
+impl Echo<Vec<f32>> {
+    pub fn new(fs: f32, max_time: f32) -> Echo<Vec<f32>> {
+        let length = (max_time * fs) as usize + 4;
+        Echo::from_lines(fs, [DelayLine::new(length), DelayLine::new(length)])
+    }
+}
+
+
+impl Echo<CapsBuffer<f32>> {
+    // allocate both delay lines from external PSRAM
+    pub fn external(fs: f32, max_time: f32) -> Result<Echo<CapsBuffer<f32>>, EspError> {
+        let length = (max_time * fs) as usize + 4;
+        Ok(Echo::from_lines(fs, [DelayLine::external(length)?, DelayLine::external(length)?]))
+    }
+}
+
+
+impl<B: DerefMut<Target = [f32]>> Echo<B> {
+    pub fn from_lines(fs: f32, lines: [DelayLine<B>; 2]) -> Echo<B> {
+        let mut time = smooth::OnePole::new(fs, 0.05, 1.);
+        time.snap_to(((lines[0].len() - 4) as f32 * 0.5).max(1.));
+        Echo {
+            fs: fs,
+            mode: Mode::Stereo,
+            feedback: 0.4,
+            damping: 0.3,
+            mix: 0.3,
+            time: time,
+            highpass: 1. - (math::TAU * 20. / fs),
+            lines: lines,
+            filters: [LoopFilter::new(); 2],
+        }
+    }
+
+    pub fn max_time(&self) -> f32 {
+        (self.lines[0].len() - 4) as f32 / self.fs
+    }
+
+    // seconds
+    pub fn set_time(&mut self, time: f32) {
+        let max = (self.lines[0].len() - 4) as f32;
+        self.time.set_target(math::clamp(time * self.fs, 1., max));
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = math::clamp(feedback, 0., 0.99);
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = math::clamp(damping, 0., 0.99);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    // jump straight to the target delay time, e.g. on preset load
+    pub fn snap(&mut self) {
+        self.time.snap();
+    }
+
+    pub fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        self.filters = [LoopFilter::new(); 2];
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let dry = 1. - self.mix;
+        let wet = self.mix;
+        for frame in buffer.chunks_mut(num_channels) {
+            let delay = self.time.next();
+            let y0 = self.lines[0].read_linear(delay);
+            let y1 = self.lines[1].read_linear(delay);
+            let f0 = self.filters[0].tick(y0, self.damping, self.highpass) * self.feedback;
+            let f1 = self.filters[1].tick(y1, self.damping, self.highpass) * self.feedback;
+
+            let x0 = frame[0];
+            let x1 = if num_channels > 1 { frame[1] } else { x0 };
+            match self.mode {
+                Mode::Stereo => {
+                    self.lines[0].write(x0 + f0);
+                    self.lines[1].write(x1 + f1);
+                }
+                Mode::PingPong => {
+                    self.lines[0].write((x0 + x1) * 0.5 + f1);
+                    self.lines[1].write(f0);
+                }
+            }
+
+            frame[0] = x0 * dry + y0 * wet;
+            if num_channels > 1 {
+                frame[1] = x1 * dry + y1 * wet;
+            }
+        }
+    }
+}
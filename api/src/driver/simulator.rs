@@ -0,0 +1,87 @@
+use core::cell::Cell;
+
+use esp_idf::EspError;
+
+use crate::audio::{Buffer, Config, OpaqueInterface};
+use crate::driver::Codec;
+use crate::wavetable;
+
+
+// - driver -------------------------------------------------------------------
+
+// Codec without hardware: `read` synthesizes a sine test tone and `write`
+// records the peak output level. Used to exercise and benchmark audio
+// code on the host.
+
+pub struct Driver {
+    pub frequency: f32,
+    pub amplitude: f32,
+    phase: Cell<f32>,
+    peak: Cell<f32>,
+    frames_written: Cell<usize>,
+}
+
+
+impl Driver {
+    pub fn peak(&self) -> f32 {
+        self.peak.get()
+    }
+
+    pub fn frames_written(&self) -> usize {
+        self.frames_written.get()
+    }
+}
+
+
+unsafe impl Codec for Driver {
+    fn new() -> Driver {
+        Driver {
+            frequency: 440.,
+            amplitude: 0.5,
+            phase: Cell::new(0.),
+            peak: Cell::new(0.),
+            frames_written: Cell::new(0),
+        }
+    }
+
+    fn init(&mut self, config: &Config) -> Result<(), EspError> {
+        Ok(())
+    }
+
+    fn read(&self, config: &Config, callback_buffer: &mut [f32]) -> Result<(), EspError> {
+        let Config { fs, num_channels, .. } = *config;
+        let dx = self.frequency / fs;
+        let mut phase = self.phase.get();
+        for frame in callback_buffer.chunks_mut(num_channels) {
+            let sample = wavetable::lookup(&wavetable::SIN, phase) * self.amplitude;
+            for channel in frame.iter_mut() {
+                *channel = sample;
+            }
+            phase += dx;
+            if phase >= 1. {
+                phase -= 1.;
+            }
+        }
+        self.phase.set(phase);
+        Ok(())
+    }
+
+    fn write(&self, config: &Config, callback_buffer: &Buffer) -> Result<(), EspError> {
+        let mut peak = self.peak.get();
+        for &sample in callback_buffer.iter() {
+            let magnitude = if sample < 0. { -sample } else { sample };
+            if magnitude > peak {
+                peak = magnitude;
+            }
+        }
+        self.peak.set(peak);
+        self.frames_written.set(self.frames_written.get() + callback_buffer.len() / config.num_channels);
+        Ok(())
+    }
+
+    fn start_c(&self, config: &Config,
+               opaque_interface_ptr: *const OpaqueInterface) -> Result<(), EspError> {
+        // not supported
+        Ok(())
+    }
+}
@@ -0,0 +1,582 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::delay::DelayLine;
+use crate::math;
+
+
+// - helpers ------------------------------------------------------------------
+
+// one-pole coefficient for a time constant in seconds
+#[inline(always)]
+fn coefficient(fs: f32, time: f32) -> f32 {
+    if time <= 0. { 0. } else { math::expf(-1. / (time * fs)) }
+}
+
+// largest absolute sample value of a frame
+#[inline(always)]
+fn frame_peak(frame: &[f32]) -> f32 {
+    let mut peak = 0.;
+    for &x in frame.iter() {
+        let x = math::fabsf(x);
+        if x > peak {
+            peak = x;
+        }
+    }
+    peak
+}
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Detection {
+    Peak,
+    Rms,
+}
+
+
+// - dynamics::Follower -------------------------------------------------------
+
+// Envelope follower with separate attack & release ballistics. In RMS
+// mode the input is first averaged over `window` seconds.
+
+pub struct Follower {
+    pub fs: f32,
+    pub detection: Detection,
+
+    attack: f32,
+    release: f32,
+    window: f32,
+    mean_square: f32,
+    envelope: f32,
+}
+
+
+impl Follower {
+    pub fn new(fs: f32, attack: f32, release: f32, detection: Detection) -> Follower {
+        Follower {
+            fs: fs,
+            detection: detection,
+            attack: coefficient(fs, attack),
+            release: coefficient(fs, release),
+            window: coefficient(fs, 0.01),
+            mean_square: 0.,
+            envelope: 0.,
+        }
+    }
+
+    // seconds
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack = coefficient(self.fs, attack);
+    }
+
+    // seconds
+    pub fn set_release(&mut self, release: f32) {
+        self.release = coefficient(self.fs, release);
+    }
+
+    // seconds, rms averaging window
+    pub fn set_window(&mut self, window: f32) {
+        self.window = coefficient(self.fs, window);
+    }
+
+    pub fn set_detection(&mut self, detection: Detection) {
+        self.detection = detection;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.envelope
+    }
+
+    pub fn reset(&mut self) {
+        self.mean_square = 0.;
+        self.envelope = 0.;
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self, x: f32) -> f32 {
+        let level = match self.detection {
+            Detection::Peak => math::fabsf(x),
+            Detection::Rms => {
+                self.mean_square = x * x + (self.mean_square - x * x) * self.window;
+                math::sqrtf(self.mean_square)
+            }
+        };
+        let coefficient = if level > self.envelope { self.attack } else { self.release };
+        self.envelope = level + (self.envelope - level) * coefficient;
+        self.envelope
+    }
+
+    // follow a block of detector input, writing the envelope to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            *y = self.tick(*x);
+        }
+    }
+}
+
+
+// - dynamics::Compressor -----------------------------------------------------
+
+// Feed-forward compressor with a soft knee gain computer. All channels
+// share one detector so the stereo image doesn't shift under compression.
+//
+// See: Giannoulis, Massberg & Reiss, "Digital Dynamic Range Compressor
+//      Design - A Tutorial and Analysis", JAES 2012
+
+pub struct Compressor {
+    pub threshold: f32, // dB
+    pub ratio: f32,     // n:1
+    pub knee: f32,      // dB
+    pub makeup: f32,    // dB
+
+    follower: Follower,
+    makeup_gain: f32,
+    gain_reduction: f32, // dB, last computed
+}
+
+
+impl Compressor {
+    pub fn new(fs: f32) -> Compressor {
+        Compressor {
+            threshold: -18.,
+            ratio: 4.,
+            knee: 6.,
+            makeup: 0.,
+            follower: Follower::new(fs, 0.005, 0.1, Detection::Rms),
+            makeup_gain: 1.,
+            gain_reduction: 0.,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = if ratio < 1. { 1. } else { ratio };
+    }
+
+    pub fn set_knee(&mut self, knee: f32) {
+        self.knee = if knee < 0. { 0. } else { knee };
+    }
+
+    pub fn set_attack(&mut self, attack: f32) {
+        self.follower.set_attack(attack);
+    }
+
+    pub fn set_release(&mut self, release: f32) {
+        self.follower.set_release(release);
+    }
+
+    pub fn set_makeup(&mut self, makeup: f32) {
+        self.makeup = makeup;
+        self.makeup_gain = math::db_to_gain(makeup);
+    }
+
+    pub fn set_detection(&mut self, detection: Detection) {
+        self.follower.set_detection(detection);
+    }
+
+    // current gain reduction in dB, useful for metering
+    pub fn gain_reduction(&self) -> f32 {
+        self.gain_reduction
+    }
+
+    pub fn reset(&mut self) {
+        self.follower.reset();
+        self.gain_reduction = 0.;
+    }
+
+    // static curve: input level in dB to gain change in dB
+    #[inline(always)]
+    pub fn gain_computer(&self, level: f32) -> f32 {
+        let overshoot = level - self.threshold;
+        let slope = 1. / self.ratio - 1.;
+        if 2. * overshoot < -self.knee {
+            0.
+        } else if self.knee > 0. && 2. * math::fabsf(overshoot) <= self.knee {
+            let x = overshoot + self.knee / 2.;
+            slope * x * x / (2. * self.knee)
+        } else {
+            slope * overshoot
+        }
+    }
+
+    #[inline(always)]
+    fn gain(&mut self, detector: f32) -> f32 {
+        let level = self.follower.tick(detector);
+        self.gain_reduction = self.gain_computer(math::gain_to_db(level));
+        math::db_to_gain(self.gain_reduction) * self.makeup_gain
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for frame in buffer.chunks_mut(num_channels) {
+            let gain = self.gain(frame_peak(frame));
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    // compress `buffer` using the level of `sidechain`, which must have
+    // the same layout
+    pub fn process_sidechain(&mut self, num_channels: usize, buffer: &mut Buffer, sidechain: &Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for (frame, key) in buffer.chunks_mut(num_channels).zip(sidechain.chunks(num_channels)) {
+            let gain = self.gain(frame_peak(key));
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+
+// - dynamics::Limiter --------------------------------------------------------
+
+// Brickwall lookahead limiter.
+//
+// The gain needed to keep each sample under the ceiling is held at its
+// minimum over the lookahead window and then smoothed with a moving
+// average of the same length. Delaying the audio by `lookahead - 1`
+// samples means the smoothed gain has always fully reached its target by
+// the time the peak is output, so the ceiling is never exceeded.
+
+pub struct Limiter {
+    pub fs: f32,
+    pub ceiling: f32, // dB
+
+    ceiling_gain: f32,
+    release: f32,
+    length: usize,
+
+    // sliding window minimum as a monotonic ring buffer of (index, gain)
+    minimum: Vec<(usize, f32)>,
+    minimum_head: usize,
+    minimum_len: usize,
+
+    // moving average of the held minimum
+    average: Vec<f32>,
+    average_index: usize,
+    average_sum: f32,
+
+    counter: usize,
+    gain: f32,
+    lines: Vec<DelayLine>,
+}
+
+
+impl Limiter {
+    pub fn new(fs: f32, lookahead: f32, num_channels: usize) -> Limiter {
+        let length = ((lookahead * fs) as usize).max(1);
+        let mut lines = Vec::with_capacity(num_channels);
+        for _ in 0..num_channels {
+            lines.push(DelayLine::new(length + 1));
+        }
+        Limiter {
+            fs: fs,
+            ceiling: -0.3,
+            ceiling_gain: math::db_to_gain(-0.3),
+            release: coefficient(fs, 0.1),
+            length: length,
+            minimum: vec![(0, 1.); length],
+            minimum_head: 0,
+            minimum_len: 0,
+            average: vec![1.; length],
+            average_index: 0,
+            average_sum: length as f32,
+            counter: 0,
+            gain: 1.,
+            lines: lines,
+        }
+    }
+
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = ceiling;
+        self.ceiling_gain = math::db_to_gain(ceiling);
+    }
+
+    // seconds
+    pub fn set_release(&mut self, release: f32) {
+        self.release = coefficient(self.fs, release);
+    }
+
+    // latency introduced by the lookahead in samples
+    pub fn latency(&self) -> usize {
+        self.length - 1
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        self.minimum_head = 0;
+        self.minimum_len = 0;
+        for x in self.average.iter_mut() {
+            *x = 1.;
+        }
+        self.average_sum = self.length as f32;
+        self.average_index = 0;
+        self.gain = 1.;
+    }
+
+    #[inline(always)]
+    fn push_minimum(&mut self, gain: f32) -> f32 {
+        let length = self.length;
+        let index = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+
+        // drop entries that have left the window
+        while self.minimum_len > 0 && index.wrapping_sub(self.minimum[self.minimum_head].0) >= length {
+            self.minimum_head = (self.minimum_head + 1) % length;
+            self.minimum_len -= 1;
+        }
+
+        // drop entries that can never be the minimum again
+        while self.minimum_len > 0 {
+            let back = (self.minimum_head + self.minimum_len - 1) % length;
+            if self.minimum[back].1 >= gain {
+                self.minimum_len -= 1;
+            } else {
+                break;
+            }
+        }
+        let back = (self.minimum_head + self.minimum_len) % length;
+        self.minimum[back] = (index, gain);
+        self.minimum_len += 1;
+
+        self.minimum[self.minimum_head].1
+    }
+
+    #[inline(always)]
+    fn push_average(&mut self, gain: f32) -> f32 {
+        self.average_sum += gain - self.average[self.average_index];
+        self.average[self.average_index] = gain;
+        self.average_index += 1;
+        if self.average_index == self.length {
+            self.average_index = 0;
+        }
+        self.average_sum / self.length as f32
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.lines.len());
+        let delay = self.length - 1;
+        for frame in buffer.chunks_mut(num_channels) {
+            let peak = frame_peak(frame);
+            let required = if peak > self.ceiling_gain { self.ceiling_gain / peak } else { 1. };
+
+            let held = self.push_minimum(required);
+            let target = self.push_average(held);
+            self.gain = if target < self.gain {
+                target
+            } else {
+                target + (self.gain - target) * self.release
+            };
+
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                let line = &mut self.lines[channel];
+                line.write(*sample);
+                let delayed = line.read(delay + 1);
+                let y = delayed * self.gain;
+                *sample = math::clamp(y, -self.ceiling_gain, self.ceiling_gain);
+            }
+        }
+    }
+}
+
+
+// - dynamics::Gate -----------------------------------------------------------
+
+// Noise gate with hysteresis and hold. When closed the signal is
+// attenuated by `range` dB rather than muted outright.
+
+pub struct Gate {
+    pub fs: f32,
+    pub threshold: f32,  // dB, opening level
+    pub hysteresis: f32, // dB, closes at threshold - hysteresis
+    pub range: f32,      // dB, attenuation when closed
+
+    follower: Follower,
+    open: bool,
+    hold: usize,
+    hold_remaining: usize,
+    attack: f32,
+    release: f32,
+    floor: f32,
+    gain: f32,
+}
+
+
+impl Gate {
+    pub fn new(fs: f32) -> Gate {
+        Gate {
+            fs: fs,
+            threshold: -50.,
+            hysteresis: 6.,
+            range: -80.,
+            follower: Follower::new(fs, 0.0005, 0.05, Detection::Peak),
+            open: false,
+            hold: (0.05 * fs) as usize,
+            hold_remaining: 0,
+            attack: coefficient(fs, 0.001),
+            release: coefficient(fs, 0.1),
+            floor: math::db_to_gain(-80.),
+            gain: math::db_to_gain(-80.),
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis: f32) {
+        self.hysteresis = if hysteresis < 0. { 0. } else { hysteresis };
+    }
+
+    pub fn set_range(&mut self, range: f32) {
+        self.range = range;
+        self.floor = math::db_to_gain(range);
+    }
+
+    // seconds
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack = coefficient(self.fs, attack);
+    }
+
+    // seconds
+    pub fn set_hold(&mut self, hold: f32) {
+        self.hold = (hold * self.fs) as usize;
+    }
+
+    // seconds
+    pub fn set_release(&mut self, release: f32) {
+        self.release = coefficient(self.fs, release);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn reset(&mut self) {
+        self.follower.reset();
+        self.open = false;
+        self.hold_remaining = 0;
+        self.gain = self.floor;
+    }
+
+    #[inline(always)]
+    fn gain(&mut self, detector: f32) -> f32 {
+        let level = math::gain_to_db(self.follower.tick(detector));
+        if level >= self.threshold {
+            self.open = true;
+            self.hold_remaining = self.hold;
+        } else if level < self.threshold - self.hysteresis {
+            if self.hold_remaining > 0 {
+                self.hold_remaining -= 1;
+            } else {
+                self.open = false;
+            }
+        }
+        let (target, coefficient) = if self.open { (1., self.attack) } else { (self.floor, self.release) };
+        self.gain = target + (self.gain - target) * coefficient;
+        self.gain
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for frame in buffer.chunks_mut(num_channels) {
+            let gain = self.gain(frame_peak(frame));
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    // gate `buffer` using the level of `sidechain`, which must have the
+    // same layout
+    pub fn process_sidechain(&mut self, num_channels: usize, buffer: &mut Buffer, sidechain: &Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for (frame, key) in buffer.chunks_mut(num_channels).zip(sidechain.chunks(num_channels)) {
+            let gain = self.gain(frame_peak(key));
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    extern crate std;
+
+    use alloc::vec::Vec;
+
+    use crate::math;
+    use super::{Compressor, Limiter};
+
+    const FS: f32 = 48_000.;
+
+    #[test]
+    fn knee() {
+        // a hard knee must not divide by zero at the threshold
+        let mut compressor = Compressor::new(FS);
+        compressor.set_knee(0.);
+        let threshold = compressor.threshold;
+        assert_eq!(compressor.gain_computer(threshold), 0.);
+        assert!(compressor.gain_computer(threshold + 12.) < 0.);
+    }
+
+    #[test]
+    fn ceiling() {
+        // repeated hits that decay over a little more than the lookahead,
+        // so the required gain keeps rising inside the window. With an
+        // instant release the gain follows the held minimum directly.
+        let mut limiter = Limiter::new(FS, 0.001, 1);
+        limiter.set_release(0.);
+        let ceiling = limiter.ceiling_gain;
+        let latency = limiter.latency();
+        let input: Vec<f32> = (0..4096).map(|n| {
+            let sign = if n % 2 == 0 { 1. } else { -1. };
+            sign * (4.5 - 4. * (n % 64) as f32 / 64.)
+        }).collect();
+
+        for (n, &x) in input.iter().enumerate() {
+            let mut frame = [x];
+            limiter.process(1, &mut frame);
+            assert!(math::fabsf(frame[0]) <= ceiling);
+
+            // the limiter gain alone must hold the delayed peak under the
+            // ceiling, without relying on the output clamp
+            if n >= latency {
+                let delayed = math::fabsf(input[n - latency]);
+                assert!(delayed * limiter.gain() <= ceiling * 1.0001,
+                        "sample {}: {} over ceiling", n, delayed * limiter.gain());
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+// steepness of exponential segments, higher is more curved
+const EXPONENTIAL_CURVATURE: f32 = 5.;
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Curve {
+    Linear,
+    Exponential,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Trigger {
+    Retrigger, // every gate on restarts the attack from the current level
+    Legato,    // gate on while the gate is already high is ignored
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Stage {
+    pub level: f32, // target level
+    pub time: f32,  // seconds
+    pub curve: Curve,
+}
+
+impl Stage {
+    pub fn new(level: f32, time: f32, curve: Curve) -> Stage {
+        Stage {
+            level: level,
+            time: time,
+            curve: curve,
+        }
+    }
+}
+
+// a gate transition at `offset` frames into the current block
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Event {
+    pub offset: usize,
+    pub gate: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Phase {
+    Idle,
+    Stage(usize),
+    Sustain(usize),
+    Release,
+}
+
+
+// - envelope::Segment --------------------------------------------------------
+
+// Interpolates from `start` to `target` over `length` samples.
+//
+// Exponential segments follow (1 - e^(-kp)) / (1 - e^(-k)) for p in
+// [0, 1] which lands exactly on the target after `length` samples.
+
+#[derive(Copy, Clone, Debug)]
+struct Segment {
+    start: f32,
+    target: f32,
+    curve: Curve,
+    length: usize,
+    position: usize,
+    m: f32,     // r^position
+    r: f32,
+    norm: f32,  // 1 / (1 - r^length)
+}
+
+impl Segment {
+    fn idle(level: f32) -> Segment {
+        Segment {
+            start: level,
+            target: level,
+            curve: Curve::Linear,
+            length: 0,
+            position: 0,
+            m: 1.,
+            r: 1.,
+            norm: 1.,
+        }
+    }
+
+    fn new(fs: f32, start: f32, stage: &Stage) -> Segment {
+        let length = (stage.time * fs) as usize;
+        let (r, norm) = match stage.curve {
+            Curve::Exponential if length > 0 => {
+                let r = math::expf(-EXPONENTIAL_CURVATURE / length as f32);
+                (r, 1. / (1. - math::expf(-EXPONENTIAL_CURVATURE)))
+            }
+            _ => (1., 1.),
+        };
+        Segment {
+            start: start,
+            target: stage.level,
+            curve: stage.curve,
+            length: length,
+            position: 0,
+            m: 1.,
+            r: r,
+            norm: norm,
+        }
+    }
+
+    #[inline(always)]
+    fn is_done(&self) -> bool {
+        self.position >= self.length
+    }
+
+    #[inline(always)]
+    fn next(&mut self) -> f32 {
+        if self.is_done() {
+            return self.target;
+        }
+        self.position += 1;
+        if self.position == self.length {
+            return self.target;
+        }
+        let p = match self.curve {
+            Curve::Linear => self.position as f32 / self.length as f32,
+            Curve::Exponential => {
+                self.m *= self.r;
+                (1. - self.m) * self.norm
+            }
+        };
+        self.start + (self.target - self.start) * p
+    }
+}
+
+
+// - envelope::Envelope -------------------------------------------------------
+
+// Gate driven multi-stage envelope.
+//
+// On gate on the envelope runs through `stages` in order. If `sustain`
+// names a stage the envelope holds that stage's level for as long as the
+// gate is high, otherwise it proceeds straight to `release` once the last
+// stage completes. Gate off at any point jumps to `release` from the
+// current level.
+
+pub struct Envelope {
+    pub fs: f32,
+    pub stages: Vec<Stage>,
+    pub sustain: Option<usize>,
+    pub release: Stage,
+    pub trigger: Trigger,
+
+    phase: Phase,
+    segment: Segment,
+    level: f32,
+    gate: bool,
+}
+
+
+impl Envelope {
+    pub fn new(fs: f32, stages: Vec<Stage>, sustain: Option<usize>, release: Stage) -> Envelope {
+        Envelope {
+            fs: fs,
+            stages: stages,
+            sustain: sustain,
+            release: release,
+            trigger: Trigger::Retrigger,
+            phase: Phase::Idle,
+            segment: Segment::idle(0.),
+            level: 0.,
+            gate: false,
+        }
+    }
+
+    pub fn adsr(fs: f32, attack: f32, decay: f32, sustain: f32, release: f32) -> Envelope {
+        Envelope::new(fs, vec![
+            Stage::new(1.,      attack, Curve::Linear),
+            Stage::new(sustain, decay,  Curve::Exponential),
+        ], Some(1), Stage::new(0., release, Curve::Exponential))
+    }
+
+    pub fn ar(fs: f32, attack: f32, release: f32) -> Envelope {
+        Envelope::new(fs, vec![
+            Stage::new(1., attack, Curve::Linear),
+        ], Some(0), Stage::new(0., release, Curve::Exponential))
+    }
+
+    // adsr / ar parameter helpers, these take effect on the next stage change
+
+    pub fn set_attack(&mut self, attack: f32) {
+        if let Some(stage) = self.stages.get_mut(0) {
+            stage.time = attack;
+        }
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        if let Some(stage) = self.stages.get_mut(1) {
+            stage.time = decay;
+        }
+    }
+
+    pub fn set_sustain(&mut self, sustain: f32) {
+        if let Some(stage) = self.stages.get_mut(1) {
+            stage.level = sustain;
+        }
+    }
+
+    pub fn set_release(&mut self, release: f32) {
+        self.release.time = release;
+    }
+
+    pub fn set_trigger(&mut self, trigger: Trigger) {
+        self.trigger = trigger;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.phase != Phase::Idle
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = Phase::Idle;
+        self.level = 0.;
+        self.segment = Segment::idle(0.);
+        self.gate = false;
+    }
+
+    pub fn gate(&mut self, gate: bool) {
+        if gate {
+            self.gate_on();
+        } else {
+            self.gate_off();
+        }
+    }
+
+    pub fn gate_on(&mut self) {
+        let already_high = self.gate;
+        self.gate = true;
+        if already_high && self.trigger == Trigger::Legato {
+            return;
+        }
+        self.enter(0);
+    }
+
+    pub fn gate_off(&mut self) {
+        self.gate = false;
+        if self.phase == Phase::Idle || self.phase == Phase::Release {
+            return;
+        }
+        self.phase = Phase::Release;
+        self.segment = Segment::new(self.fs, self.level, &self.release);
+    }
+
+    fn enter(&mut self, index: usize) {
+        match self.stages.get(index) {
+            Some(stage) => {
+                self.phase = Phase::Stage(index);
+                self.segment = Segment::new(self.fs, self.level, stage);
+            }
+            None => {
+                self.phase = Phase::Release;
+                self.segment = Segment::new(self.fs, self.level, &self.release);
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        match self.phase {
+            Phase::Idle | Phase::Sustain(_) => (),
+            Phase::Stage(index) => {
+                self.level = self.segment.next();
+                if self.segment.is_done() {
+                    if self.sustain == Some(index) && self.gate {
+                        self.phase = Phase::Sustain(index);
+                    } else {
+                        self.enter(index + 1);
+                    }
+                }
+            }
+            Phase::Release => {
+                self.level = self.segment.next();
+                if self.segment.is_done() {
+                    self.phase = Phase::Idle;
+                }
+            }
+        }
+        self.level
+    }
+
+    pub fn process(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = self.next();
+        }
+    }
+
+    // render a block of envelope values, applying gate events at their
+    // frame offsets. Events must be sorted by offset.
+    pub fn process_events(&mut self, output: &mut [f32], events: &[Event]) {
+        let mut events = events.iter().peekable();
+        for (frame, sample) in output.iter_mut().enumerate() {
+            while let Some(event) = events.peek() {
+                if event.offset > frame {
+                    break;
+                }
+                self.gate(event.gate);
+                events.next();
+            }
+            *sample = self.next();
+        }
+        for event in events {
+            self.gate(event.gate);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use esp_idf::EspError;
+
+use crate::audio::Buffer;
+use crate::fft::real::{self, Complex, Fft};
+use crate::fft::window::Window;
+use crate::math;
+
+
+// - fft::tap -----------------------------------------------------------------
+
+// Single producer, single consumer ring of mono samples used to get audio
+// out of the audio closure without locking. The writer never blocks, when
+// the reader falls behind new samples are dropped.
+
+struct Ring {
+    data: Vec<UnsafeCell<f32>>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+// only the writer stores to slots between `write` and `read`, only the
+// reader loads from slots between `read` and `write`
+unsafe impl Sync for Ring {}
+unsafe impl Send for Ring {}
+
+
+pub struct TapWriter {
+    ring: Arc<Ring>,
+    dropped: usize,
+}
+
+pub struct TapReader {
+    ring: Arc<Ring>,
+}
+
+
+// Creates a tap holding up to `capacity` samples, hand the writer to the
+// audio closure and the reader to the analyzer.
+pub fn tap(capacity: usize) -> (TapWriter, TapReader) {
+    let ring = Arc::new(Ring {
+        data: (0..capacity.max(1) + 1).map(|_| UnsafeCell::new(0.)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    (TapWriter { ring: ring.clone(), dropped: 0 }, TapReader { ring: ring })
+}
+
+
+impl TapWriter {
+    // Pushes an interleaved buffer with `num_channels` channels, mixed down
+    // to mono. Returns the number of frames written.
+    pub fn push(&mut self, num_channels: usize, buffer: &Buffer) -> usize {
+        if num_channels == 0 {
+            return 0;
+        }
+        let ring = &*self.ring;
+        let length = ring.data.len();
+        let read = ring.read.load(Ordering::Acquire);
+        let mut write = ring.write.load(Ordering::Relaxed);
+        let scale = 1. / num_channels as f32;
+        let mut count = 0;
+        for frame in buffer.chunks(num_channels) {
+            let next = if write + 1 == length { 0 } else { write + 1 };
+            if next == read {
+                break;
+            }
+            let x: f32 = frame.iter().sum();
+            unsafe { *ring.data[write].get() = x * scale; }
+            write = next;
+            count += 1;
+        }
+        ring.write.store(write, Ordering::Release);
+        self.dropped += buffer.len() / num_channels - count;
+        count
+    }
+
+    // number of frames dropped because the reader fell behind
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+
+impl TapReader {
+    // number of samples waiting to be read
+    pub fn available(&self) -> usize {
+        let ring = &*self.ring;
+        let read = ring.read.load(Ordering::Relaxed);
+        let write = ring.write.load(Ordering::Acquire);
+        if write >= read { write - read } else { write + ring.data.len() - read }
+    }
+
+    // Pops up to `output.len()` samples, returns the number read.
+    pub fn pop(&mut self, output: &mut [f32]) -> usize {
+        let ring = &*self.ring;
+        let length = ring.data.len();
+        let write = ring.write.load(Ordering::Acquire);
+        let mut read = ring.read.load(Ordering::Relaxed);
+        let mut count = 0;
+        for y in output.iter_mut() {
+            if read == write {
+                break;
+            }
+            *y = unsafe { *ring.data[read].get() };
+            read = if read + 1 == length { 0 } else { read + 1 };
+            count += 1;
+        }
+        ring.read.store(read, Ordering::Release);
+        count
+    }
+}
+
+
+// - fft::Analyzer ------------------------------------------------------------
+
+// Running spectrum analyzer fed from a tap.
+//
+// A new spectrum is computed every `hop` samples over the last `size`
+// samples. The spectrum is in dBFS, a full scale sine reads 0 dB, and is
+// averaged over successive frames by `smoothing`. Call `update` from a
+// task outside the audio closure, e.g. the display loop.
+
+pub struct Analyzer {
+    pub fs: f32,
+    pub smoothing: f32, // 0 - 1, amount of the previous spectrum kept
+
+    fft: Fft,
+    window: Vec<f32>,
+    scale: f32,
+    hop: usize,
+    input: Vec<f32>,
+    count: usize,
+    frame: Vec<f32>,
+    bins: Vec<Complex>,
+    levels: Vec<f32>,
+    spectrum: Vec<f32>,
+}
+
+
+impl Analyzer {
+    pub fn new(fs: f32, size: usize, window: Window, hop: usize) -> Result<Analyzer, EspError> {
+        let fft = Fft::new(size)?;
+        let bins = fft.bins();
+        let mut coefficients = vec![0.; size];
+        window.fill(&mut coefficients);
+        let scale = Window::amplitude_scale(&coefficients);
+        Ok(Analyzer {
+            fs: fs,
+            smoothing: 0.5,
+            fft: fft,
+            window: coefficients,
+            scale: scale,
+            hop: hop.max(1).min(size),
+            input: vec![0.; size],
+            count: 0,
+            frame: vec![0.; size],
+            bins: vec![Complex::default(); bins],
+            levels: vec![0.; bins],
+            spectrum: vec![-140.; bins],
+        })
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = math::clamp(smoothing, 0., 0.99);
+    }
+
+    pub fn set_window(&mut self, window: Window) {
+        window.fill(&mut self.window);
+        self.scale = Window::amplitude_scale(&self.window);
+    }
+
+    pub fn set_hop(&mut self, hop: usize) {
+        self.hop = hop.max(1).min(self.fft.size());
+    }
+
+    pub fn size(&self) -> usize {
+        self.fft.size()
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        for y in self.spectrum.iter_mut() {
+            *y = -140.;
+        }
+    }
+
+    // Drains the tap, returns true if at least one new spectrum was
+    // computed.
+    pub fn update(&mut self, reader: &mut TapReader) -> bool {
+        let size = self.fft.size();
+        let mut updated = false;
+        loop {
+            self.count += reader.pop(&mut self.input[self.count..]);
+            if self.count < size {
+                break;
+            }
+            self.analyze();
+            self.input.copy_within(self.hop.., 0);
+            self.count = size - self.hop;
+            updated = true;
+        }
+        updated
+    }
+
+    fn analyze(&mut self) {
+        for ((y, x), w) in self.frame.iter_mut().zip(&self.input).zip(&self.window) {
+            *y = x * w;
+        }
+        self.fft.forward(&self.frame, &mut self.bins);
+        real::magnitudes_db(&self.bins, 1. / self.scale, &mut self.levels);
+        let smoothing = self.smoothing;
+        for (y, x) in self.spectrum.iter_mut().zip(&self.levels) {
+            *y = *y * smoothing + x * (1. - smoothing);
+        }
+    }
+
+    // smoothed magnitude of each bin in dBFS, dc to nyquist
+    pub fn spectrum(&self) -> &[f32] {
+        &self.spectrum
+    }
+
+    // complex bins of the most recent frame, not smoothed
+    pub fn bins(&self) -> &[Complex] {
+        &self.bins
+    }
+
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.fs / self.fft.size() as f32
+    }
+
+    // Frequency and level of the loudest bin, refined by fitting a parabola
+    // through its neighbours.
+    pub fn peak(&self) -> (f32, f32) {
+        let spectrum = &self.spectrum;
+        let last = spectrum.len() - 1;
+        let mut index = 1;
+        for k in 1..last {
+            if spectrum[k] > spectrum[index] {
+                index = k;
+            }
+        }
+        let (a, b, c) = (spectrum[index - 1], spectrum[index], spectrum[index + 1]);
+        let denominator = a - 2. * b + c;
+        let offset = if denominator < 0. { math::clamp(0.5 * (a - c) / denominator, -0.5, 0.5) } else { 0. };
+        (self.bin_frequency(index) + offset * self.fs / self.fft.size() as f32,
+         b - 0.25 * (a - c) * offset)
+    }
+}
@@ -0,0 +1,12 @@
+// - modules ------------------------------------------------------------------
+
+pub mod analyzer;
+pub mod real;
+pub mod window;
+
+
+// - exports ------------------------------------------------------------------
+
+pub use analyzer::{Analyzer, TapReader, TapWriter, tap};
+pub use real::{Complex, Fft, MAX_SIZE, MIN_SIZE, magnitudes, magnitudes_db, phases};
+pub use window::Window;
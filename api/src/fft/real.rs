@@ -0,0 +1,269 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::ops::{Add, Mul, Sub};
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MIN_SIZE: usize = 64;
+pub const MAX_SIZE: usize = 4096;
+
+
+// - fft::Complex -------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    #[inline(always)]
+    pub const fn new(re: f32, im: f32) -> Complex {
+        Complex { re: re, im: im }
+    }
+
+    #[inline(always)]
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    // squared magnitude
+    #[inline(always)]
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    #[inline(always)]
+    pub fn norm(self) -> f32 {
+        math::sqrtf(self.norm_sqr())
+    }
+
+    #[inline(always)]
+    pub fn arg(self) -> f32 {
+        math::atan2f(self.im, self.re)
+    }
+
+    #[inline(always)]
+    pub fn scale(self, k: f32) -> Complex {
+        Complex::new(self.re * k, self.im * k)
+    }
+
+    // multiply by j
+    #[inline(always)]
+    fn rotate(self) -> Complex {
+        Complex::new(-self.im, self.re)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    #[inline(always)]
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    #[inline(always)]
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    #[inline(always)]
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im,
+                     self.re * other.im + self.im * other.re)
+    }
+}
+
+
+// - fft::Fft -----------------------------------------------------------------
+
+// Real input FFT for power of two sizes from `MIN_SIZE` to `MAX_SIZE`.
+//
+// The `size` real samples are packed into a complex sequence of half the
+// length, transformed with radix-4 stages (plus a single radix-2 stage for
+// odd powers of two) and then split into the `size / 2 + 1` bins of the
+// real spectrum. All tables are computed up front, `forward` and `inverse`
+// do not allocate.
+
+pub struct Fft {
+    size: usize,
+    twiddles: Vec<Complex>, // W_N^k for k < N / 2, N = size
+    reversed: Vec<u16>,     // bit reversal permutation for N / 2 points
+    scratch: Vec<Complex>,
+}
+
+
+impl Fft {
+    pub fn new(size: usize) -> Result<Fft, EspError> {
+        if size < MIN_SIZE || size > MAX_SIZE || size.count_ones() != 1 {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+
+        let half = size / 2;
+        let twiddles = (0..half).map(|k| {
+            let theta = -math::TAU * k as f32 / size as f32;
+            Complex::new(math::cosf(theta), math::sinf(theta))
+        }).collect();
+
+        let bits = half.trailing_zeros();
+        let reversed = (0..half).map(|k| {
+            ((k as u32).reverse_bits() >> (32 - bits)) as u16
+        }).collect();
+
+        Ok(Fft {
+            size: size,
+            twiddles: twiddles,
+            reversed: reversed,
+            scratch: vec![Complex::default(); half],
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // number of bins in the spectrum, dc to nyquist inclusive
+    pub fn bins(&self) -> usize {
+        self.size / 2 + 1
+    }
+
+    // Transforms `size` samples from `input` into `bins()` complex bins in
+    // `output`. The spectrum is not normalized.
+    pub fn forward(&mut self, input: &[f32], output: &mut [Complex]) {
+        let half = self.size / 2;
+        let input = &input[..self.size];
+        let output = &mut output[..half + 1];
+
+        // pack even / odd samples as real / imaginary parts
+        for k in 0..half {
+            let n = self.reversed[k] as usize;
+            self.scratch[k] = Complex::new(input[2 * n], input[2 * n + 1]);
+        }
+        self.transform(false);
+
+        // split into the spectrum of the real input
+        let z = &self.scratch;
+        output[0] = Complex::new(z[0].re + z[0].im, 0.);
+        output[half] = Complex::new(z[0].re - z[0].im, 0.);
+        for k in 1..half {
+            let a = z[k];
+            let b = z[half - k].conj();
+            let even = (a + b).scale(0.5);
+            let odd = (a - b).scale(0.5);
+            // odd / j = -j * odd
+            let odd = Complex::new(odd.im, -odd.re);
+            output[k] = even + self.twiddles[k] * odd;
+        }
+    }
+
+    // Transforms `bins()` complex bins from `input` back into `size` samples
+    // in `output`, the exact inverse of `forward`.
+    pub fn inverse(&mut self, input: &[Complex], output: &mut [f32]) {
+        let half = self.size / 2;
+        let input = &input[..half + 1];
+        let output = &mut output[..self.size];
+
+        for k in 0..half {
+            let a = input[k];
+            let b = input[half - k].conj();
+            let even = (a + b).scale(0.5);
+            let odd = (a - b).scale(0.5) * self.twiddles[k].conj();
+            let n = self.reversed[k] as usize;
+            self.scratch[n] = even + odd.rotate();
+        }
+        self.transform(true);
+
+        let scale = 1. / half as f32;
+        for (n, z) in self.scratch.iter().enumerate() {
+            output[2 * n] = z.re * scale;
+            output[2 * n + 1] = z.im * scale;
+        }
+    }
+
+    // In place complex transform of the bit reversed `scratch`. Twiddles for
+    // the N / 2 point transform are every other entry of the N point table.
+    fn transform(&mut self, inverse: bool) {
+        let points = self.size / 2;
+        let data = &mut self.scratch;
+        let twiddles = &self.twiddles;
+        let twiddle = |k: usize| {
+            let w = twiddles[2 * k];
+            if inverse { w.conj() } else { w }
+        };
+
+        let mut span = 1;
+        if points.trailing_zeros() % 2 == 1 {
+            for pair in data.chunks_mut(2) {
+                let (a, b) = (pair[0], pair[1]);
+                pair[0] = a + b;
+                pair[1] = a - b;
+            }
+            span = 2;
+        }
+
+        // radix-4 stages, inputs are bit reversed so each butterfly combines
+        // the sub transforms (0, 2, 1, 3) of the four quarters
+        while span < points {
+            let size = span * 4;
+            let stride = points / size;
+            for group in data.chunks_mut(size) {
+                for k in 0..span {
+                    let w1 = twiddle(k * stride);
+                    let w2 = twiddle(2 * k * stride);
+                    let w3 = w1 * w2;
+                    let a = group[k];
+                    let b = group[k + span] * w2;
+                    let c = group[k + 2 * span] * w1;
+                    let d = group[k + 3 * span] * w3;
+                    let ab0 = a + b;
+                    let ab1 = a - b;
+                    let cd0 = c + d;
+                    let cd1 = if inverse { (c - d).rotate() } else { (d - c).rotate() };
+                    group[k] = ab0 + cd0;
+                    group[k + span] = ab1 + cd1;
+                    group[k + 2 * span] = ab0 - cd0;
+                    group[k + 3 * span] = ab1 - cd1;
+                }
+            }
+            span = size;
+        }
+    }
+}
+
+
+// - fft::helpers -------------------------------------------------------------
+
+pub fn magnitudes(spectrum: &[Complex], output: &mut [f32]) {
+    for (y, x) in output.iter_mut().zip(spectrum) {
+        *y = x.norm();
+    }
+}
+
+// magnitudes in dB relative to `reference`, floored at -140 dB
+pub fn magnitudes_db(spectrum: &[Complex], reference: f32, output: &mut [f32]) {
+    let floor = 1e-14 * reference * reference;
+    let scale = 1. / (reference * reference);
+    for (y, x) in output.iter_mut().zip(spectrum) {
+        *y = 10. * math::log10f(x.norm_sqr().max(floor) * scale);
+    }
+}
+
+pub fn phases(spectrum: &[Complex], output: &mut [f32]) {
+    for (y, x) in output.iter_mut().zip(spectrum) {
+        *y = x.arg();
+    }
+}
@@ -0,0 +1,42 @@
+use crate::math;
+
+
+// - fft::Window --------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris, // 4 term, -92 dB sidelobes
+}
+
+
+impl Window {
+    // Fills `output` with the periodic window, the form that sums to a
+    // constant when overlapped for spectral analysis.
+    pub fn fill(&self, output: &mut [f32]) {
+        let length = output.len() as f32;
+        for (n, y) in output.iter_mut().enumerate() {
+            let phase = math::TAU * n as f32 / length;
+            *y = match self {
+                Window::Rectangular => 1.,
+                Window::Hann => 0.5 - 0.5 * math::cosf(phase),
+                Window::Hamming => 0.54 - 0.46 * math::cosf(phase),
+                Window::Blackman => 0.42 - 0.5 * math::cosf(phase)
+                    + 0.08 * math::cosf(2. * phase),
+                Window::BlackmanHarris => 0.35875 - 0.48829 * math::cosf(phase)
+                    + 0.14128 * math::cosf(2. * phase)
+                    - 0.01168 * math::cosf(3. * phase),
+            };
+        }
+    }
+
+    // Scale that turns the magnitude of a bin into the amplitude of a sine
+    // centered on it, 2 / sum(window).
+    pub fn amplitude_scale(window: &[f32]) -> f32 {
+        let sum: f32 = window.iter().sum();
+        if sum > 0. { 2. / sum } else { 0. }
+    }
+}
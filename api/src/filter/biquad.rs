@@ -0,0 +1,364 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+// number of samples over which a coefficient change is interpolated
+pub const DEFAULT_RAMP_LENGTH: usize = 256;
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    Peak,
+    LowShelf,
+    HighShelf,
+    AllPass,
+}
+
+
+// - filter::Coefficients -----------------------------------------------------
+
+// RBJ audio eq cookbook coefficients, normalized by a0
+//
+// See: https://www.w3.org/2011/audio/audio-eq-cookbook.html
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    pub fn identity() -> Coefficients {
+        Coefficients { b0: 1., b1: 0., b2: 0., a1: 0., a2: 0. }
+    }
+
+    pub fn new(kind: Kind, fs: f32, frequency: f32, q: f32, gain: f32) -> Coefficients {
+        let frequency = math::clamp(frequency, 1., fs * 0.49);
+        let q = if q < 0.01 { 0.01 } else { q };
+
+        let w0 = math::TAU * frequency / fs;
+        let cos_w0 = math::cosf(w0);
+        let sin_w0 = math::sinf(w0);
+        let alpha = sin_w0 / (2. * q);
+        let a = math::powf(10., gain / 40.);
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            Kind::LowPass => (
+                (1. - cos_w0) / 2.,
+                1. - cos_w0,
+                (1. - cos_w0) / 2.,
+                1. + alpha,
+                -2. * cos_w0,
+                1. - alpha,
+            ),
+            Kind::HighPass => (
+                (1. + cos_w0) / 2.,
+                -(1. + cos_w0),
+                (1. + cos_w0) / 2.,
+                1. + alpha,
+                -2. * cos_w0,
+                1. - alpha,
+            ),
+            Kind::BandPass => (
+                alpha,
+                0.,
+                -alpha,
+                1. + alpha,
+                -2. * cos_w0,
+                1. - alpha,
+            ),
+            Kind::Notch => (
+                1.,
+                -2. * cos_w0,
+                1.,
+                1. + alpha,
+                -2. * cos_w0,
+                1. - alpha,
+            ),
+            Kind::Peak => (
+                1. + alpha * a,
+                -2. * cos_w0,
+                1. - alpha * a,
+                1. + alpha / a,
+                -2. * cos_w0,
+                1. - alpha / a,
+            ),
+            Kind::LowShelf => {
+                let k = 2. * math::sqrtf(a) * alpha;
+                (
+                    a * ((a + 1.) - (a - 1.) * cos_w0 + k),
+                    2. * a * ((a - 1.) - (a + 1.) * cos_w0),
+                    a * ((a + 1.) - (a - 1.) * cos_w0 - k),
+                    (a + 1.) + (a - 1.) * cos_w0 + k,
+                    -2. * ((a - 1.) + (a + 1.) * cos_w0),
+                    (a + 1.) + (a - 1.) * cos_w0 - k,
+                )
+            }
+            Kind::HighShelf => {
+                let k = 2. * math::sqrtf(a) * alpha;
+                (
+                    a * ((a + 1.) + (a - 1.) * cos_w0 + k),
+                    -2. * a * ((a - 1.) + (a + 1.) * cos_w0),
+                    a * ((a + 1.) + (a - 1.) * cos_w0 - k),
+                    (a + 1.) - (a - 1.) * cos_w0 + k,
+                    2. * ((a - 1.) - (a + 1.) * cos_w0),
+                    (a + 1.) - (a - 1.) * cos_w0 - k,
+                )
+            }
+            Kind::AllPass => (
+                1. - alpha,
+                -2. * cos_w0,
+                1. + alpha,
+                1. + alpha,
+                -2. * cos_w0,
+                1. - alpha,
+            ),
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+
+// - filter::Biquad -----------------------------------------------------------
+
+// Transposed direct-form II biquad operating on interleaved buffers.
+//
+// Parameter changes compute a new set of target coefficients which the
+// filter then linearly interpolates towards, one step per frame, over
+// `ramp_length` samples.
+
+#[derive(Copy, Clone, Debug)]
+struct State {
+    z1: f32,
+    z2: f32,
+}
+
+pub struct Biquad {
+    pub kind: Kind,
+    pub fs: f32,
+    pub frequency: f32,
+    pub q: f32,
+    pub gain: f32, // dB, only used by Peak, LowShelf & HighShelf
+
+    coefficients: Coefficients,
+    target: Coefficients,
+    delta: Coefficients,
+    ramp_length: usize,
+    ramp_remaining: usize,
+
+    state: Vec<State>,
+}
+
+
+impl Biquad {
+    pub fn new(kind: Kind, fs: f32, frequency: f32, q: f32, num_channels: usize) -> Biquad {
+        let coefficients = Coefficients::new(kind, fs, frequency, q, 0.);
+        let mut state = Vec::with_capacity(num_channels);
+        state.resize(num_channels, State { z1: 0., z2: 0. });
+        Biquad {
+            kind: kind,
+            fs: fs,
+            frequency: frequency,
+            q: q,
+            gain: 0.,
+            coefficients: coefficients,
+            target: coefficients,
+            delta: Coefficients { b0: 0., b1: 0., b2: 0., a1: 0., a2: 0. },
+            ramp_length: DEFAULT_RAMP_LENGTH,
+            ramp_remaining: 0,
+            state: state,
+        }
+    }
+
+    pub fn set_ramp_length(&mut self, ramp_length: usize) {
+        self.ramp_length = ramp_length;
+    }
+
+    pub fn set_kind(&mut self, kind: Kind) {
+        self.kind = kind;
+        self.update();
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.update();
+    }
+
+    pub fn set_q(&mut self, q: f32) {
+        self.q = q;
+        self.update();
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+        self.update();
+    }
+
+    pub fn set(&mut self, kind: Kind, frequency: f32, q: f32, gain: f32) {
+        self.kind = kind;
+        self.frequency = frequency;
+        self.q = q;
+        self.gain = gain;
+        self.update();
+    }
+
+    pub fn coefficients(&self) -> Coefficients {
+        self.coefficients
+    }
+
+    // jump straight to the target coefficients, e.g. on preset load
+    pub fn snap(&mut self) {
+        self.coefficients = self.target;
+        self.ramp_remaining = 0;
+    }
+
+    pub fn reset(&mut self) {
+        for state in self.state.iter_mut() {
+            state.z1 = 0.;
+            state.z2 = 0.;
+        }
+    }
+
+    fn update(&mut self) {
+        self.target = Coefficients::new(self.kind, self.fs, self.frequency, self.q, self.gain);
+        if self.ramp_length == 0 {
+            self.snap();
+            return;
+        }
+        let n = self.ramp_length as f32;
+        let Coefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        self.delta = Coefficients {
+            b0: (self.target.b0 - b0) / n,
+            b1: (self.target.b1 - b1) / n,
+            b2: (self.target.b2 - b2) / n,
+            a1: (self.target.a1 - a1) / n,
+            a2: (self.target.a2 - a2) / n,
+        };
+        self.ramp_remaining = self.ramp_length;
+    }
+
+    // advance coefficient interpolation by one frame
+    #[inline(always)]
+    pub fn step(&mut self) {
+        if self.ramp_remaining == 0 {
+            return;
+        }
+        self.ramp_remaining -= 1;
+        if self.ramp_remaining == 0 {
+            self.coefficients = self.target;
+            return;
+        }
+        let c = &mut self.coefficients;
+        let d = &self.delta;
+        c.b0 += d.b0;
+        c.b1 += d.b1;
+        c.b2 += d.b2;
+        c.a1 += d.a1;
+        c.a2 += d.a2;
+    }
+
+    // filter a single sample on the given channel without advancing
+    // coefficient interpolation
+    #[inline(always)]
+    pub fn tick(&mut self, channel: usize, x: f32) -> f32 {
+        let Coefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let state = &mut self.state[channel];
+        let y = b0 * x + state.z1;
+        state.z1 = b1 * x - a1 * y + state.z2;
+        state.z2 = b2 * x - a2 * y;
+        y
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.state.len());
+        for frame in buffer.chunks_mut(num_channels) {
+            self.step();
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample);
+            }
+        }
+    }
+}
+
+
+// - filter::Cascade ----------------------------------------------------------
+
+pub struct Cascade {
+    pub sections: Vec<Biquad>,
+}
+
+
+impl Cascade {
+    pub fn new(sections: Vec<Biquad>) -> Cascade {
+        Cascade {
+            sections: sections,
+        }
+    }
+
+    // even-order butterworth low/high pass built from `order / 2` sections
+    pub fn butterworth(kind: Kind, fs: f32, frequency: f32, order: usize, num_channels: usize) -> Cascade {
+        let num_sections = if order < 2 { 1 } else { order / 2 };
+        let n = (num_sections * 2) as f32;
+        let mut sections = Vec::with_capacity(num_sections);
+        for k in 0..num_sections {
+            let theta = math::PI * (2 * k + 1) as f32 / (2. * n);
+            let q = 1. / (2. * math::cosf(theta));
+            sections.push(Biquad::new(kind, fs, frequency, q, num_channels));
+        }
+        Cascade::new(sections)
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        for section in self.sections.iter_mut() {
+            section.set_frequency(frequency);
+        }
+    }
+
+    pub fn set_ramp_length(&mut self, ramp_length: usize) {
+        for section in self.sections.iter_mut() {
+            section.set_ramp_length(ramp_length);
+        }
+    }
+
+    pub fn snap(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.snap();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.reset();
+        }
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        for section in self.sections.iter_mut() {
+            section.process(num_channels, buffer);
+        }
+    }
+}
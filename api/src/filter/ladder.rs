@@ -0,0 +1,147 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+
+
+// - filter::Ladder -----------------------------------------------------------
+
+// Four pole zero-delay feedback ladder low pass with a tanh saturator
+// on the feedback path.
+//
+// The feedback loop is solved for the linear case and the result is
+// then saturated, which keeps self-oscillation bounded and stable under
+// audio-rate cutoff modulation.
+//
+// See: Vadim Zavalishin, "The Art of VA Filter Design", ch. 5
+
+#[derive(Copy, Clone, Debug)]
+struct State {
+    s: [f32; 4],
+}
+
+pub struct Ladder {
+    pub fs: f32,
+    pub cutoff: f32,
+    pub resonance: f32, // 0 - 1, self-oscillates near 1
+    pub drive: f32,
+
+    g: f32,
+    k: f32,
+
+    state: Vec<State>,
+}
+
+
+impl Ladder {
+    pub fn new(fs: f32, cutoff: f32, resonance: f32, num_channels: usize) -> Ladder {
+        let mut state = Vec::with_capacity(num_channels);
+        state.resize(num_channels, State { s: [0.; 4] });
+        let mut ladder = Ladder {
+            fs: fs,
+            cutoff: cutoff,
+            resonance: resonance,
+            drive: 1.,
+            g: 0.,
+            k: 0.,
+            state: state,
+        };
+        ladder.update();
+        ladder
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+        self.update();
+    }
+
+    pub fn set_resonance(&mut self, resonance: f32) {
+        self.resonance = resonance;
+        self.update();
+    }
+
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive;
+    }
+
+    pub fn reset(&mut self) {
+        for state in self.state.iter_mut() {
+            state.s = [0.; 4];
+        }
+    }
+
+    fn update(&mut self) {
+        let cutoff = math::clamp(self.cutoff, 1., self.fs * 0.45);
+        let g = math::tanf(math::PI * cutoff / self.fs);
+        self.g = g / (1. + g);
+        self.k = 4. * math::clamp(self.resonance, 0., 1.);
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self, channel: usize, x: f32) -> f32 {
+        let g = self.g;
+        let k = self.k;
+        let state = &mut self.state[channel];
+        let s = &mut state.s;
+
+        // each stage is y = g * x + (1 - g) * s, so the ladder output is
+        // g^4 * u + sigma where sigma depends only on the stage states
+        let g2 = g * g;
+        let beta = 1. - g;
+        let sigma = g2 * g * beta * s[0]
+                  + g2 * beta * s[1]
+                  + g * beta * s[2]
+                  + beta * s[3];
+
+        let u = (x * self.drive - k * sigma) / (1. + k * g2 * g2);
+        let u = math::tanhf(u);
+
+        let mut input = u;
+        for stage in s.iter_mut() {
+            let v = (input - *stage) * g;
+            let y = v + *stage;
+            *stage = y + v;
+            input = y;
+        }
+
+        input
+    }
+
+    #[inline(always)]
+    pub fn tick_modulated(&mut self, channel: usize, x: f32, cutoff: f32) -> f32 {
+        self.cutoff = cutoff;
+        self.update();
+        self.tick(channel, x)
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.state.len());
+        for frame in buffer.chunks_mut(num_channels) {
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample);
+            }
+        }
+    }
+
+    // process with a per-frame cutoff, e.g. from an lfo or envelope
+    pub fn process_modulated(&mut self, num_channels: usize, buffer: &mut Buffer, cutoff: &[f32]) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.state.len());
+        for (index, frame) in buffer.chunks_mut(num_channels).enumerate() {
+            // a short `cutoff` holds its last value for the remaining frames
+            if let Some(&cutoff) = cutoff.get(index) {
+                self.cutoff = cutoff;
+                self.update();
+            }
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample);
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+// - modules ------------------------------------------------------------------
+
+pub mod biquad;
+pub mod ladder;
+pub mod svf;
+
+
+// - exports ------------------------------------------------------------------
+
+pub use biquad::{Biquad, Cascade, Coefficients, Kind};
+pub use ladder::Ladder;
+pub use svf::Svf;
@@ -0,0 +1,170 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    LowPass,
+    BandPass,
+    HighPass,
+    Notch,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Outputs {
+    pub low: f32,
+    pub band: f32,
+    pub high: f32,
+}
+
+impl Outputs {
+    #[inline(always)]
+    pub fn select(&self, mode: Mode) -> f32 {
+        match mode {
+            Mode::LowPass  => self.low,
+            Mode::BandPass => self.band,
+            Mode::HighPass => self.high,
+            Mode::Notch    => self.low + self.high,
+        }
+    }
+}
+
+
+// - filter::Svf --------------------------------------------------------------
+
+// Topology-preserving transform state variable filter with simultaneous
+// low, band & high pass outputs.
+//
+// The trapezoidal integrators keep the filter stable under audio-rate
+// cutoff modulation, use `tick_modulated` to supply a new cutoff per sample.
+//
+// See: https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+
+#[derive(Copy, Clone, Debug)]
+struct State {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+pub struct Svf {
+    pub mode: Mode,
+    pub fs: f32,
+    pub cutoff: f32,
+    pub q: f32,
+
+    g: f32,
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+
+    state: Vec<State>,
+}
+
+
+impl Svf {
+    pub fn new(mode: Mode, fs: f32, cutoff: f32, q: f32, num_channels: usize) -> Svf {
+        let mut state = Vec::with_capacity(num_channels);
+        state.resize(num_channels, State { ic1eq: 0., ic2eq: 0. });
+        let mut svf = Svf {
+            mode: mode,
+            fs: fs,
+            cutoff: cutoff,
+            q: q,
+            g: 0.,
+            k: 0.,
+            a1: 0.,
+            a2: 0.,
+            a3: 0.,
+            state: state,
+        };
+        svf.update();
+        svf
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+        self.update();
+    }
+
+    pub fn set_q(&mut self, q: f32) {
+        self.q = q;
+        self.update();
+    }
+
+    pub fn reset(&mut self) {
+        for state in self.state.iter_mut() {
+            state.ic1eq = 0.;
+            state.ic2eq = 0.;
+        }
+    }
+
+    fn update(&mut self) {
+        let cutoff = math::clamp(self.cutoff, 1., self.fs * 0.49);
+        let q = if self.q < 0.5 { 0.5 } else { self.q };
+        self.g = math::tanf(math::PI * cutoff / self.fs);
+        self.k = 1. / q;
+        self.a1 = 1. / (1. + self.g * (self.g + self.k));
+        self.a2 = self.g * self.a1;
+        self.a3 = self.g * self.a2;
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self, channel: usize, x: f32) -> Outputs {
+        let state = &mut self.state[channel];
+        let v3 = x - state.ic2eq;
+        let v1 = self.a1 * state.ic1eq + self.a2 * v3;
+        let v2 = state.ic2eq + self.a2 * state.ic1eq + self.a3 * v3;
+        state.ic1eq = 2. * v1 - state.ic1eq;
+        state.ic2eq = 2. * v2 - state.ic2eq;
+        Outputs {
+            low: v2,
+            band: v1,
+            high: x - self.k * v1 - v2,
+        }
+    }
+
+    #[inline(always)]
+    pub fn tick_modulated(&mut self, channel: usize, x: f32, cutoff: f32) -> Outputs {
+        self.cutoff = cutoff;
+        self.update();
+        self.tick(channel, x)
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.state.len());
+        let mode = self.mode;
+        for frame in buffer.chunks_mut(num_channels) {
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample).select(mode);
+            }
+        }
+    }
+
+    // process with a per-frame cutoff, e.g. from an lfo or envelope
+    pub fn process_modulated(&mut self, num_channels: usize, buffer: &mut Buffer, cutoff: &[f32]) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.state.len());
+        let mode = self.mode;
+        for (index, frame) in buffer.chunks_mut(num_channels).enumerate() {
+            // a short `cutoff` holds its last value for the remaining frames
+            if let Some(&cutoff) = cutoff.get(index) {
+                self.cutoff = cutoff;
+                self.update();
+            }
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample).select(mode);
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::envelope::Envelope;
+use crate::math;
+use crate::voice::{Note, Voice};
+use crate::wavetable;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MAX_OPERATORS: usize = 6;
+
+const MODULATION_DEPTH: f32 = 2.;  // cycles of phase deviation at full modulator level
+const FEEDBACK_DEPTH: f32 = 0.5;   // cycles of phase deviation at full feedback
+
+
+// - fm::Algorithm ------------------------------------------------------------
+
+// Routing between operators.
+//
+// Operators are numbered from 0. Bit `j` of `modulators[i]` routes the
+// output of operator `j` into the phase of operator `i`, modulators must
+// have a higher number than the operator they modulate. Bit `i` of
+// `carriers` sends operator `i` to the output.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Algorithm {
+    pub num_operators: usize,
+    pub modulators: [u8; MAX_OPERATORS],
+    pub carriers: u8,
+    pub feedback: usize, // operator modulating itself
+}
+
+// The eight 4-operator algorithms of the TX81Z / DX9 family, with
+// feedback on operator 3.
+pub const ALGORITHMS_4: [Algorithm; 8] = [
+    // 3 > 2 > 1 > 0
+    Algorithm { num_operators: 4, modulators: [0b0010, 0b0100, 0b1000, 0, 0, 0], carriers: 0b0001, feedback: 3 },
+    // (2 + 3) > 1 > 0
+    Algorithm { num_operators: 4, modulators: [0b0010, 0b1100, 0, 0, 0, 0], carriers: 0b0001, feedback: 3 },
+    // (3 + (2 > 1)) > 0
+    Algorithm { num_operators: 4, modulators: [0b1010, 0b0100, 0, 0, 0, 0], carriers: 0b0001, feedback: 3 },
+    // (1 + (3 > 2)) > 0
+    Algorithm { num_operators: 4, modulators: [0b0110, 0, 0b1000, 0, 0, 0], carriers: 0b0001, feedback: 3 },
+    // 1 > 0, 3 > 2
+    Algorithm { num_operators: 4, modulators: [0b0010, 0, 0b1000, 0, 0, 0], carriers: 0b0101, feedback: 3 },
+    // 3 > (0, 1, 2)
+    Algorithm { num_operators: 4, modulators: [0b1000, 0b1000, 0b1000, 0, 0, 0], carriers: 0b0111, feedback: 3 },
+    // 0, 1, 3 > 2
+    Algorithm { num_operators: 4, modulators: [0, 0, 0b1000, 0, 0, 0], carriers: 0b0111, feedback: 3 },
+    // 0, 1, 2, 3
+    Algorithm { num_operators: 4, modulators: [0; MAX_OPERATORS], carriers: 0b1111, feedback: 3 },
+];
+
+// A selection of 6-operator algorithms, numbered as on the DX7.
+pub const ALGORITHMS_6: [Algorithm; 5] = [
+    // 1: 1 > 0, 5 > 4 > 3 > 2
+    Algorithm { num_operators: 6, modulators: [0b000010, 0, 0b001000, 0b010000, 0b100000, 0], carriers: 0b000101, feedback: 5 },
+    // 5: 1 > 0, 3 > 2, 5 > 4
+    Algorithm { num_operators: 6, modulators: [0b000010, 0, 0b001000, 0, 0b100000, 0], carriers: 0b010101, feedback: 5 },
+    // 22: 1 > 0, 5 > (2, 3, 4)
+    Algorithm { num_operators: 6, modulators: [0b000010, 0, 0b100000, 0b100000, 0b100000, 0], carriers: 0b011101, feedback: 5 },
+    // 31: 0, 1, 2, 3, 5 > 4
+    Algorithm { num_operators: 6, modulators: [0, 0, 0, 0, 0b100000, 0], carriers: 0b011111, feedback: 5 },
+    // 32: 0, 1, 2, 3, 4, 5
+    Algorithm { num_operators: 6, modulators: [0; MAX_OPERATORS], carriers: 0b111111, feedback: 5 },
+];
+
+
+// - fm::Operator -------------------------------------------------------------
+
+// Sine oscillator with its own envelope. The frequency is either a ratio
+// of the note frequency or fixed in Hz.
+
+pub struct Operator {
+    pub ratio: f32,
+    pub fixed: Option<f32>,          // Hz
+    pub detune: f32,                 // cents
+    pub level: f32,                  // 0 - 1
+    pub velocity_sensitivity: f32,   // 0 - 1
+    pub envelope: Envelope,
+
+    phase: f32,
+    increment: f32,
+    gain: f32,
+}
+
+
+impl Operator {
+    pub fn new(fs: f32) -> Operator {
+        Operator {
+            ratio: 1.,
+            fixed: None,
+            detune: 0.,
+            level: 1.,
+            velocity_sensitivity: 0.5,
+            envelope: Envelope::adsr(fs, 0.002, 0.3, 0.7, 0.3),
+            phase: 0.,
+            increment: 0.,
+            gain: 0.,
+        }
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio;
+        self.fixed = None;
+    }
+
+    pub fn set_fixed(&mut self, frequency: f32) {
+        self.fixed = Some(frequency);
+    }
+
+    pub fn set_detune(&mut self, detune: f32) {
+        self.detune = detune;
+    }
+
+    pub fn set_level(&mut self, level: f32) {
+        self.level = math::clamp(level, 0., 1.);
+    }
+
+    pub fn set_velocity_sensitivity(&mut self, velocity_sensitivity: f32) {
+        self.velocity_sensitivity = math::clamp(velocity_sensitivity, 0., 1.);
+    }
+
+    fn set_note(&mut self, fs: f32, frequency: f32, velocity: f32) {
+        let frequency = match self.fixed {
+            Some(fixed) => fixed,
+            None => frequency * self.ratio,
+        };
+        let detune = if self.detune == 0. { 1. } else { math::powf(2., self.detune / 1200.) };
+        self.increment = frequency * detune / fs;
+        let sensitivity = self.velocity_sensitivity;
+        self.gain = self.level * (1. - sensitivity + sensitivity * velocity);
+    }
+
+    // `modulation` is a phase offset in cycles
+    #[inline(always)]
+    fn tick(&mut self, modulation: f32) -> f32 {
+        let mut phase = self.phase + modulation;
+        phase -= math::floorf(phase);
+        let y = wavetable::lookup(&wavetable::SIN, phase) * self.envelope.next() * self.gain;
+        self.phase += self.increment;
+        if self.phase >= 1. {
+            self.phase -= 1.;
+        }
+        y
+    }
+}
+
+
+// - fm::FmVoice --------------------------------------------------------------
+
+// DX style phase modulation voice with up to 6 operators.
+//
+// Feedback averages the last two outputs of the feedback operator, which
+// keeps high settings from breaking into oscillation.
+
+pub struct FmVoice {
+    pub fs: f32,
+    pub algorithm: Algorithm,
+    pub feedback: f32, // 0 - 1
+    pub operators: Vec<Operator>,
+
+    history: [f32; 2],
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl FmVoice {
+    pub fn new(fs: f32, algorithm: Algorithm) -> FmVoice {
+        FmVoice {
+            fs: fs,
+            algorithm: algorithm,
+            feedback: 0.,
+            operators: (0..algorithm.num_operators.min(MAX_OPERATORS)).map(|_| Operator::new(fs)).collect(),
+            history: [0.; 2],
+            gain: 0.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    // switching between 4 and 6 operator algorithms keeps the settings of
+    // the operators they have in common
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        let num_operators = algorithm.num_operators.min(MAX_OPERATORS);
+        while self.operators.len() < num_operators {
+            self.operators.push(Operator::new(self.fs));
+        }
+        self.operators.truncate(num_operators);
+        self.algorithm = algorithm;
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = math::clamp(feedback, 0., 1.);
+    }
+
+    fn num_carriers(&self) -> u32 {
+        let mask = (1u32 << self.operators.len()) - 1;
+        (self.algorithm.carriers as u32 & mask).count_ones().max(1)
+    }
+}
+
+
+impl Voice for FmVoice {
+    fn note_on(&mut self, note: &Note, legato: bool) {
+        let frequency = math::note_to_frequency(note.pitch());
+        for operator in self.operators.iter_mut() {
+            operator.set_note(self.fs, frequency, note.velocity);
+            if !legato {
+                operator.envelope.gate_on();
+            }
+        }
+        self.gain = note.gain / self.num_carriers() as f32;
+        let (left, right) = math::pan_gains(note.pan);
+        self.left = left;
+        self.right = right;
+    }
+
+    fn note_off(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.envelope.gate_off();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        let carriers = self.algorithm.carriers;
+        self.operators.iter().enumerate().any(|(index, operator)| {
+            carriers & (1 << index) != 0 && operator.envelope.is_active()
+        })
+    }
+
+    fn level(&self) -> f32 {
+        let carriers = self.algorithm.carriers;
+        let level = self.operators.iter().enumerate()
+            .filter(|(index, _)| carriers & (1 << index) != 0)
+            .fold(0., |level, (_, operator)| level + operator.envelope.level() * operator.gain);
+        level * self.gain
+    }
+
+    fn reset(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.envelope.reset();
+            operator.phase = 0.;
+        }
+        self.history = [0.; 2];
+    }
+
+    fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let Algorithm { modulators, carriers, feedback: feedback_operator, .. } = self.algorithm;
+        let num_operators = self.operators.len();
+        let feedback = self.feedback * FEEDBACK_DEPTH * 0.5;
+
+        for frame in buffer.chunks_mut(num_channels) {
+            let mut outputs = [0.; MAX_OPERATORS];
+            let mut y = 0.;
+
+            // modulators always have a higher number than their targets
+            for index in (0..num_operators).rev() {
+                let mask = modulators[index];
+                let mut modulation = 0.;
+                if mask != 0 {
+                    for source in (index + 1)..num_operators {
+                        if mask & (1 << source) != 0 {
+                            modulation += outputs[source];
+                        }
+                    }
+                    modulation *= MODULATION_DEPTH;
+                }
+                if index == feedback_operator {
+                    modulation += (self.history[0] + self.history[1]) * feedback;
+                }
+
+                let output = self.operators[index].tick(modulation);
+                if index == feedback_operator {
+                    self.history[1] = self.history[0];
+                    self.history[0] = output;
+                }
+                outputs[index] = output;
+                if carriers & (1 << index) != 0 {
+                    y += output;
+                }
+            }
+
+            let y = y * self.gain;
+            if num_channels > 1 {
+                frame[0] += y * self.right;
+                frame[1] += y * self.left;
+            } else {
+                frame[0] += y;
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+use crate::sample::Source;
+use crate::wavetable;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MAX_GRAINS: usize = 32;
+
+const GUARD: f32 = 4.; // frames kept clear of the record head for interpolation
+
+
+// - granular::Grain ----------------------------------------------------------
+
+#[derive(Copy, Clone, Debug)]
+struct Grain {
+    active: bool,
+    position: f32,  // frame index into the source storage
+    increment: f32, // frames per output frame
+    phase: f32,     // 0 - 1 through the window
+    dx: f32,        // window phase per output frame
+    left: f32,
+    right: f32,
+}
+
+impl Grain {
+    fn new() -> Grain {
+        Grain {
+            active: false,
+            position: 0.,
+            increment: 1.,
+            phase: 0.,
+            dx: 0.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+}
+
+
+// - granular::Granular -------------------------------------------------------
+
+// Granular processor: spawns Hann windowed grains read from any sample
+// `Source`.
+//
+// `position` selects where grains start as a fraction of the recorded
+// frames, from oldest to newest, `spray` randomly offsets each start by up
+// to that many seconds. When granulating live input record the input
+// buffer into the source first, grains never read past the record head
+// and with circular recording start at least a grain away from the
+// oldest frames, which are the next to be overwritten.
+//
+// To granulate live input that is also played by a sampler share the
+// `Sample` as an `Rc<RefCell<Sample>>`, e.g.
+//
+//     let sample = Rc::new(RefCell::new(Sample::external(fs, 2, frames)?));
+//     sample.borrow_mut().circular = true;
+//     let mut granular = Granular::new(fs, sample.clone());
+//     let mut sampler = Sampler::new(fs, sample.clone());
+//
+// then in the audio closure, after `Codec::read`
+//
+//     sample.borrow_mut().record(num_channels, &input);
+//     granular.process(num_channels, &mut output);
+
+pub struct Granular<S> {
+    pub fs: f32,
+    pub source: S,
+    pub position: f32, // 0 - 1
+    pub size: f32,     // seconds
+    pub density: f32,  // grains per second
+    pub pitch: f32,    // semitones
+    pub spray: f32,    // seconds
+    pub spread: f32,   // 0 - 1, stereo spread of the grains
+    pub mix: f32,      // 0 - 1, dry / wet
+
+    grains: Vec<Grain>,
+    random: math::Random,
+    countdown: f32,
+    increment: f32,
+}
+
+
+impl<S: Source> Granular<S> {
+    pub fn new(fs: f32, source: S) -> Granular<S> {
+        Granular {
+            fs: fs,
+            source: source,
+            position: 0.5,
+            size: 0.1,
+            density: 20.,
+            pitch: 0.,
+            spray: 0.02,
+            spread: 0.5,
+            mix: 1.,
+            grains: (0..MAX_GRAINS).map(|_| Grain::new()).collect(),
+            random: math::Random::new(0x9a1e),
+            countdown: 0.,
+            increment: 1.,
+        }
+    }
+
+    pub fn set_position(&mut self, position: f32) {
+        self.position = math::clamp(position, 0., 1.);
+    }
+
+    pub fn set_size(&mut self, size: f32) {
+        self.size = math::clamp(size, 0.001, 2.);
+    }
+
+    pub fn set_density(&mut self, density: f32) {
+        self.density = math::clamp(density, 0., 1000.);
+    }
+
+    pub fn set_pitch(&mut self, pitch: f32) {
+        self.pitch = math::clamp(pitch, -48., 48.);
+    }
+
+    pub fn set_spray(&mut self, spray: f32) {
+        self.spray = spray.max(0.);
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.spread = math::clamp(spread, 0., 1.);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn active_grains(&self) -> usize {
+        self.grains.iter().filter(|grain| grain.active).count()
+    }
+
+    pub fn reset(&mut self) {
+        for grain in self.grains.iter_mut() {
+            grain.active = false;
+        }
+        self.countdown = 0.;
+    }
+
+    fn spawn(&mut self) {
+        let index = match self.grains.iter().position(|grain| !grain.active) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let ratio = self.source.fs() / self.fs;
+        let length = self.size * self.fs;
+        let span = length * self.increment * ratio + GUARD;
+        let available = self.source.len() as f32 - span;
+        // the record head overwrites the oldest frames while the grain plays
+        let minimum = if self.source.circular() { span.max(length * ratio + GUARD) } else { 1. };
+        if available < minimum {
+            return;
+        }
+        let spray = self.random.next_bipolar() * self.spray * self.source.fs();
+        let start = math::clamp(self.position * available + spray, minimum, available);
+        let capacity = self.source.capacity() as f32;
+        let mut position = self.source.offset() as f32 + start;
+        if position >= capacity {
+            position -= capacity;
+        }
+
+        let pan = self.random.next_bipolar() * self.spread;
+        let (left, right) = math::pan_gains(pan);
+        self.grains[index] = Grain {
+            active: true,
+            position: position,
+            increment: self.increment * ratio,
+            phase: 0.,
+            dx: 1. / length,
+            left: left,
+            right: right,
+        };
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        self.increment = math::powf(2., self.pitch / 12.);
+        let overlap = self.density * self.size;
+        let gain = 1. / math::sqrtf(overlap.max(1.));
+        let interval = if self.density > 0. { self.fs / self.density } else { 0. };
+        let capacity = self.source.capacity() as f32;
+        let source_channels = self.source.num_channels();
+        let dry = 1. - self.mix;
+        let wet = self.mix * gain;
+
+        for frame in buffer.chunks_mut(num_channels) {
+            if interval > 0. {
+                self.countdown -= 1.;
+                if self.countdown <= 0. {
+                    self.spawn();
+                    self.countdown += interval;
+                }
+            }
+
+            let mut y0 = 0.;
+            let mut y1 = 0.;
+            for grain in self.grains.iter_mut().filter(|grain| grain.active) {
+                // hann window, sin(pi * phase) ^ 2
+                let window = wavetable::lookup(&wavetable::SIN, grain.phase * 0.5);
+                let window = window * window;
+                let x0 = self.source.read_linear(grain.position, 0) * window;
+                let x1 = if source_channels > 1 { self.source.read_linear(grain.position, 1) * window } else { x0 };
+                y0 += x0 * grain.right;
+                y1 += x1 * grain.left;
+
+                grain.position += grain.increment;
+                if grain.position >= capacity {
+                    grain.position -= capacity;
+                }
+                grain.phase += grain.dx;
+                if grain.phase >= 1. {
+                    grain.active = false;
+                }
+            }
+
+            frame[0] = frame[0] * dry + y0 * wet;
+            if num_channels > 1 {
+                frame[1] = frame[1] * dry + y1 * wet;
+            }
+        }
+    }
+}
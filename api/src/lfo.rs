@@ -0,0 +1,189 @@
+use crate::math;
+use crate::wavetable;
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+    SmoothRandom,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Rate {
+    Hz(f32),
+    Beats(f32), // length of one cycle in beats, e.g. 0.25 for 1/16th notes in 4/4
+}
+
+
+// - lfo::Lfo -----------------------------------------------------------------
+
+// Low frequency oscillator with bipolar output in [-1, 1].
+//
+// Call `next` once per sample for audio-rate modulation or `advance` once
+// per block for control-rate modulation.
+
+pub struct Lfo {
+    pub shape: Shape,
+    pub fs: f32,
+    pub rate: Rate,
+    pub tempo: f32,        // bpm, used when rate is Rate::Beats
+    pub phase_offset: f32, // [0, 1)
+    pub one_shot: bool,
+
+    phase: f32,
+    increment: f32,
+    finished: bool,
+    random: math::Random,
+    held: f32,
+    target: f32,
+}
+
+
+impl Lfo {
+    pub fn new(shape: Shape, fs: f32, rate: Rate) -> Lfo {
+        let mut random = math::Random::new(0x1f0_1f0);
+        let held = random.next_bipolar();
+        let target = random.next_bipolar();
+        let mut lfo = Lfo {
+            shape: shape,
+            fs: fs,
+            rate: rate,
+            tempo: 120.,
+            phase_offset: 0.,
+            one_shot: false,
+            phase: 0.,
+            increment: 0.,
+            finished: false,
+            random: random,
+            held: held,
+            target: target,
+        };
+        lfo.update();
+        lfo
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        self.shape = shape;
+    }
+
+    pub fn set_rate(&mut self, rate: Rate) {
+        self.rate = rate;
+        self.update();
+    }
+
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.tempo = tempo;
+        self.update();
+    }
+
+    pub fn set_phase_offset(&mut self, phase_offset: f32) {
+        self.phase_offset = phase_offset - math::floorf(phase_offset);
+    }
+
+    pub fn set_one_shot(&mut self, one_shot: bool) {
+        self.one_shot = one_shot;
+    }
+
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = math::Random::new(seed);
+    }
+
+    pub fn frequency(&self) -> f32 {
+        match self.rate {
+            Rate::Hz(frequency) => frequency,
+            Rate::Beats(beats) => {
+                if beats <= 0. { 0. } else { self.tempo / (60. * beats) }
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // restart the cycle, e.g. on note on
+    pub fn trigger(&mut self) {
+        self.phase = 0.;
+        self.finished = false;
+    }
+
+    // lock phase to a tempo clock given the current position in beats
+    pub fn sync(&mut self, beat: f32) {
+        if let Rate::Beats(beats) = self.rate {
+            if beats > 0. {
+                let cycles = beat / beats;
+                self.phase = cycles - math::floorf(cycles);
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        self.increment = self.frequency() / self.fs;
+    }
+
+    #[inline(always)]
+    pub fn value(&self) -> f32 {
+        let p = self.phase + self.phase_offset;
+        let p = if p >= 1. { p - 1. } else { p };
+        match self.shape {
+            Shape::Sine => wavetable::lookup(&wavetable::SIN, p),
+            Shape::Triangle => {
+                if p < 0.25 {
+                    4. * p
+                } else if p < 0.75 {
+                    2. - 4. * p
+                } else {
+                    4. * p - 4.
+                }
+            }
+            Shape::Saw => 2. * p - 1.,
+            Shape::Square => if p < 0.5 { 1. } else { -1. },
+            Shape::SampleAndHold => self.held,
+            Shape::SmoothRandom => {
+                let t = self.phase;
+                let t = t * t * (3. - 2. * t);
+                self.held + (self.target - self.held) * t
+            }
+        }
+    }
+
+    // advance by `frames` samples and return the new value
+    #[inline(always)]
+    pub fn advance(&mut self, frames: usize) -> f32 {
+        if self.finished {
+            return self.value();
+        }
+        self.phase += self.increment * frames as f32;
+        while self.phase >= 1. {
+            if self.one_shot {
+                self.phase = 1. - f32::EPSILON;
+                self.finished = true;
+                break;
+            }
+            self.phase -= 1.;
+            self.held = self.target;
+            self.target = self.random.next_bipolar();
+        }
+        self.value()
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        let value = self.value();
+        self.advance(1);
+        value
+    }
+
+    // fill a block with audio-rate values
+    pub fn process(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = self.next();
+        }
+    }
+}
@@ -33,14 +33,40 @@ macro_rules! log {
 pub mod allocators;
 pub mod audio;
 pub mod blinky;
+pub mod convolution;
+pub mod delay;
 pub mod display;
 pub mod driver;
+pub mod dynamics;
+pub mod envelope;
+pub mod fft;
+pub mod filter;
+pub mod fm;
+pub mod granular;
 pub mod i2c;
 pub mod i2s;
 pub mod ledc;
+pub mod lfo;
 pub mod logger;
 pub mod lwip;
+pub mod math;
+pub mod matrix;
+pub mod mixer;
+pub mod modulation;
 pub mod nvs;
+pub mod onset;
+pub mod patch;
+pub mod physical;
+pub mod pitch;
+pub mod processor;
+pub mod resample;
+pub mod reverb;
+pub mod sample;
+pub mod sampler;
+pub mod saturation;
+pub mod smooth;
+pub mod voice;
+pub mod wav;
 pub mod wavetable;
 pub mod wifi;
 
@@ -50,6 +76,8 @@ pub mod wifi;
 use core::intrinsics;
 use core::panic::PanicInfo;
 
+// host tests link std, which brings its own panic handler
+#[cfg(not(test))]
 #[lang = "panic_impl"]
 extern fn rust_begin_panic(_info: &PanicInfo) -> ! {
     unsafe { intrinsics::abort() }
@@ -0,0 +1,112 @@
+use esp_idf::bindings as idf;
+
+
+// - global constants ---------------------------------------------------------
+
+pub use core::f32::consts::{PI, TAU};
+
+
+// - libm wrappers ------------------------------------------------------------
+
+#[inline(always)] pub fn sinf(x: f32) -> f32 { unsafe { idf::sinf(x) } }
+#[inline(always)] pub fn cosf(x: f32) -> f32 { unsafe { idf::cosf(x) } }
+#[inline(always)] pub fn tanf(x: f32) -> f32 { unsafe { idf::tanf(x) } }
+#[inline(always)] pub fn tanhf(x: f32) -> f32 { unsafe { idf::tanhf(x) } }
+#[inline(always)] pub fn expf(x: f32) -> f32 { unsafe { idf::expf(x) } }
+#[inline(always)] pub fn logf(x: f32) -> f32 { unsafe { idf::logf(x) } }
+#[inline(always)] pub fn log10f(x: f32) -> f32 { unsafe { idf::log10f(x) } }
+#[inline(always)] pub fn powf(x: f32, y: f32) -> f32 { unsafe { idf::powf(x, y) } }
+#[inline(always)] pub fn sqrtf(x: f32) -> f32 { unsafe { idf::sqrtf(x) } }
+#[inline(always)] pub fn fabsf(x: f32) -> f32 { unsafe { idf::fabsf(x) } }
+#[inline(always)] pub fn floorf(x: f32) -> f32 { unsafe { idf::floorf(x) } }
+#[inline(always)] pub fn atan2f(y: f32, x: f32) -> f32 { unsafe { idf::atan2f(y, x) } }
+#[inline(always)] pub fn log2f(x: f32) -> f32 { unsafe { idf::log2f(x) } }
+
+
+// - helpers ------------------------------------------------------------------
+
+#[inline(always)]
+pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
+    if x < min { min } else if x > max { max } else { x }
+}
+
+#[inline(always)]
+pub fn db_to_gain(db: f32) -> f32 {
+    powf(10., db / 20.)
+}
+
+#[inline(always)]
+pub fn gain_to_db(gain: f32) -> f32 {
+    20. * log10f(if gain < 1e-9 { 1e-9 } else { gain })
+}
+
+// midi note number, fractional for detune, to frequency in Hz
+#[inline(always)]
+pub fn note_to_frequency(note: f32) -> f32 {
+    440. * powf(2., (note - 69.) / 12.)
+}
+
+// zeroth order modified bessel function of the first kind, for kaiser
+// windows
+pub fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let half = x / 2.;
+    for k in 1..32 {
+        term *= (half / k as f32) * (half / k as f32);
+        sum += term;
+        if term < sum * 1e-9 {
+            break;
+        }
+    }
+    sum
+}
+
+// equal power pan law for pan in [-1, 1], returns (left, right) gains
+#[inline(always)]
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let theta = (clamp(pan, -1., 1.) + 1.) * PI / 4.;
+    (cosf(theta), sinf(theta))
+}
+
+
+// - math::Random -------------------------------------------------------------
+
+// xorshift32 pseudo-random number generator
+//
+// See: https://www.jstatsoft.org/article/view/v008i14
+
+#[derive(Copy, Clone, Debug)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        Random {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    #[inline(always)]
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    // uniform in [0, 1)
+    #[inline(always)]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / 16_777_216.
+    }
+
+    // uniform in [-1, 1)
+    #[inline(always)]
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_f32() * 2. - 1.
+    }
+}
@@ -0,0 +1,347 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::envelope::Envelope;
+use crate::lfo::{self, Lfo};
+use crate::math;
+use crate::smooth;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const NUM_KNOBS: usize = 8;
+pub const NUM_CCS: usize = 128;
+
+
+// - matrix::Source -----------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Source {
+    Lfo(usize),      // bipolar, [-1, 1]
+    Envelope(usize), // [0, 1]
+    Velocity,        // [0, 1], of the last note on
+    Knob(usize),     // [0, 1], cv or knob input
+    Cc(u8),          // [0, 1], midi control change
+}
+
+
+// - matrix::Curve ------------------------------------------------------------
+
+// Response applied to a source before scaling by the route depth, the sign
+// of bipolar sources is kept.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Curve {
+    Linear,
+    Exponential, // slow start, x²
+    Logarithmic, // fast start, √x
+    SCurve,      // smoothstep
+}
+
+impl Curve {
+    pub fn apply(&self, x: f32) -> f32 {
+        let magnitude = math::fabsf(x);
+        let y = match self {
+            Curve::Linear => magnitude,
+            Curve::Exponential => magnitude * magnitude,
+            Curve::Logarithmic => math::sqrtf(magnitude),
+            Curve::SCurve => {
+                let x = math::clamp(magnitude, 0., 1.);
+                x * x * (3. - 2. * x)
+            }
+        };
+        if x < 0. { -y } else { y }
+    }
+}
+
+
+// - matrix::Route ------------------------------------------------------------
+
+#[derive(Copy, Clone, Debug)]
+pub struct Route {
+    pub source: Source,
+    pub target: usize, // parameter index
+    pub depth: f32,    // in parameter units for a full scale source
+    pub curve: Curve,
+}
+
+
+// - matrix::Parameter --------------------------------------------------------
+
+pub struct Parameter {
+    pub name: String,
+    pub base: f32, // unmodulated value
+    pub minimum: f32,
+    pub maximum: f32,
+
+    smoother: smooth::OnePole,
+    previous: f32,
+}
+
+impl Parameter {
+    pub fn set_base(&mut self, base: f32) {
+        self.base = base;
+    }
+
+    pub fn set_range(&mut self, minimum: f32, maximum: f32) {
+        self.minimum = minimum.min(maximum);
+        self.maximum = maximum.max(minimum);
+    }
+
+    // smoothed value at the end of the last block
+    pub fn value(&self) -> f32 {
+        self.smoother.value()
+    }
+}
+
+
+// - matrix::Matrix -----------------------------------------------------------
+
+// Routes modulation sources to named parameters, e.g.
+//
+//     let cutoff = matrix.add_parameter("filter.cutoff", 800., 20., 18000.);
+//     let wobble = matrix.add_lfo(lfo::Shape::Sine, lfo::Rate::Hz(0.5));
+//     let amp = matrix.add_envelope(0.01, 0.3, 0.6, 0.5);
+//     matrix.connect(Source::Lfo(wobble), "filter.cutoff", 400., Curve::Linear)?;
+//     matrix.connect(Source::Envelope(amp), "filter.cutoff", 2000., Curve::Exponential)?;
+//     matrix.connect(Source::Cc(74), "filter.cutoff", 6000., Curve::Logarithmic)?;
+//
+// then once per block in the audio closure
+//
+//     matrix.update(frames);
+//     filter.set_cutoff(matrix.value(cutoff));
+//
+// or `matrix.ramp(cutoff, &mut buffer)` for a per sample ramp.
+//
+// Sources are evaluated at control rate, once per block. Each parameter
+// is its base plus the sum of its routes, clamped to its range and
+// smoothed with a one pole filter of `smoothing` seconds so that steps in
+// knobs, velocity or sample and hold lfos do not zip.
+
+pub struct Matrix {
+    pub fs: f32,
+    pub smoothing: f32, // seconds
+
+    pub parameters: Vec<Parameter>,
+    pub routes: Vec<Option<Route>>, // disconnected routes leave an empty slot
+    pub lfos: Vec<Lfo>,
+    pub envelopes: Vec<Envelope>,
+
+    velocity: f32,
+    knobs: [f32; NUM_KNOBS],
+    ccs: [f32; NUM_CCS],
+}
+
+
+impl Matrix {
+    pub fn new(fs: f32) -> Matrix {
+        Matrix {
+            fs: fs,
+            smoothing: 0.01,
+            parameters: Vec::new(),
+            routes: Vec::new(),
+            lfos: Vec::new(),
+            envelopes: Vec::new(),
+            velocity: 0.,
+            knobs: [0.; NUM_KNOBS],
+            ccs: [0.; NUM_CCS],
+        }
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing;
+        for parameter in self.parameters.iter_mut() {
+            parameter.smoother.set_time(smoothing);
+        }
+    }
+
+    // Adds a parameter and returns its index, adding a name twice returns
+    // the existing parameter.
+    pub fn add_parameter(&mut self, name: &str, base: f32, minimum: f32, maximum: f32) -> usize {
+        if let Some(index) = self.find(name) {
+            return index;
+        }
+        let value = math::clamp(base, minimum, maximum);
+        self.parameters.push(Parameter {
+            name: String::from(name),
+            base: base,
+            minimum: minimum,
+            maximum: maximum,
+            smoother: smooth::OnePole::new(self.fs, self.smoothing, value),
+            previous: value,
+        });
+        self.parameters.len() - 1
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.parameters.iter().position(|parameter| parameter.name == name)
+    }
+
+    pub fn add_lfo(&mut self, shape: lfo::Shape, rate: lfo::Rate) -> usize {
+        self.lfos.push(Lfo::new(shape, self.fs, rate));
+        self.lfos.len() - 1
+    }
+
+    pub fn add_envelope(&mut self, attack: f32, decay: f32, sustain: f32, release: f32) -> usize {
+        self.envelopes.push(Envelope::adsr(self.fs, attack, decay, sustain, release));
+        self.envelopes.len() - 1
+    }
+
+    // Routes `source` to the parameter called `target`, returns the route
+    // index or `ESP_ERR_NOT_FOUND` for an unknown parameter. A route index
+    // stays valid until it is disconnected, after which it may be reused.
+    pub fn connect(&mut self, source: Source, target: &str, depth: f32, curve: Curve) -> Result<usize, EspError> {
+        let target = match self.find(target) {
+            Some(target) => target,
+            None => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        };
+        let route = Some(Route {
+            source: source,
+            target: target,
+            depth: depth,
+            curve: curve,
+        });
+        match self.routes.iter().position(|slot| slot.is_none()) {
+            Some(index) => {
+                self.routes[index] = route;
+                Ok(index)
+            }
+            None => {
+                self.routes.push(route);
+                Ok(self.routes.len() - 1)
+            }
+        }
+    }
+
+    pub fn disconnect(&mut self, route: usize) {
+        if let Some(slot) = self.routes.get_mut(route) {
+            *slot = None;
+        }
+    }
+
+    pub fn set_depth(&mut self, route: usize, depth: f32) {
+        if let Some(Some(route)) = self.routes.get_mut(route) {
+            route.depth = depth;
+        }
+    }
+
+    // gates the envelopes on and sets the velocity
+    pub fn note_on(&mut self, velocity: f32) {
+        self.velocity = math::clamp(velocity, 0., 1.);
+        for envelope in self.envelopes.iter_mut() {
+            envelope.gate_on();
+        }
+    }
+
+    pub fn note_off(&mut self) {
+        for envelope in self.envelopes.iter_mut() {
+            envelope.gate_off();
+        }
+    }
+
+    pub fn set_velocity(&mut self, velocity: f32) {
+        self.velocity = math::clamp(velocity, 0., 1.);
+    }
+
+    pub fn set_knob(&mut self, knob: usize, value: f32) {
+        if let Some(knob) = self.knobs.get_mut(knob) {
+            *knob = math::clamp(value, 0., 1.);
+        }
+    }
+
+    // midi control change, `value` in [0, 127]
+    pub fn set_cc(&mut self, cc: u8, value: u8) {
+        if let Some(slot) = self.ccs.get_mut(cc as usize) {
+            *slot = value.min(127) as f32 / 127.;
+        }
+    }
+
+    // tempo for lfos synced to beats
+    pub fn set_tempo(&mut self, tempo: f32) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_tempo(tempo);
+        }
+    }
+
+    pub fn source(&self, source: Source) -> f32 {
+        match source {
+            Source::Lfo(index) => self.lfos.get(index).map_or(0., |lfo| lfo.value()),
+            Source::Envelope(index) => self.envelopes.get(index).map_or(0., |envelope| envelope.level()),
+            Source::Velocity => self.velocity,
+            Source::Knob(index) => self.knobs.get(index).copied().unwrap_or(0.),
+            Source::Cc(cc) => self.ccs.get(cc as usize).copied().unwrap_or(0.),
+        }
+    }
+
+    // Advances the sources by a block of `frames` and updates the
+    // parameters.
+    pub fn update(&mut self, frames: usize) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.advance(frames);
+        }
+        for envelope in self.envelopes.iter_mut() {
+            for _ in 0..frames {
+                envelope.next();
+            }
+        }
+
+        for parameter in self.parameters.iter_mut() {
+            parameter.previous = parameter.smoother.value();
+            parameter.smoother.set_target(parameter.base);
+        }
+        for route in self.routes.iter().flatten() {
+            let value = route.depth * route.curve.apply(self.source(route.source));
+            if let Some(parameter) = self.parameters.get_mut(route.target) {
+                let target = parameter.smoother.target();
+                parameter.smoother.set_target(target + value);
+            }
+        }
+        for parameter in self.parameters.iter_mut() {
+            let target = math::clamp(parameter.smoother.target(), parameter.minimum, parameter.maximum);
+            parameter.smoother.set_target(target);
+            parameter.smoother.advance(frames);
+        }
+    }
+
+    // smoothed value of a parameter at the end of the last block
+    pub fn value(&self, parameter: usize) -> f32 {
+        self.parameters.get(parameter).map_or(0., |parameter| parameter.value())
+    }
+
+    // per sample values ramping over the last block
+    pub fn ramp(&self, parameter: usize, output: &mut [f32]) {
+        let parameter = match self.parameters.get(parameter) {
+            Some(parameter) => parameter,
+            None => return,
+        };
+        let start = parameter.previous;
+        let step = (parameter.value() - start) / output.len().max(1) as f32;
+        for (n, sample) in output.iter_mut().enumerate() {
+            *sample = start + step * (n + 1) as f32;
+        }
+    }
+
+    // jump all parameters to their current targets, e.g. on preset load
+    pub fn snap(&mut self) {
+        for parameter in self.parameters.iter_mut() {
+            parameter.smoother.snap();
+            parameter.previous = parameter.smoother.value();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for envelope in self.envelopes.iter_mut() {
+            envelope.reset();
+        }
+        self.velocity = 0.;
+        for parameter in self.parameters.iter_mut() {
+            let value = math::clamp(parameter.base, parameter.minimum, parameter.maximum);
+            parameter.smoother.snap_to(value);
+            parameter.previous = value;
+        }
+    }
+}
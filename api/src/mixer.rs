@@ -0,0 +1,372 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+use crate::smooth;
+
+
+// - global constants ---------------------------------------------------------
+
+// strips and buses are stereo, channel 0 is right and channel 1 is left
+const NUM_CHANNELS: usize = 2;
+
+
+// - mixer::PanLaw ------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PanLaw {
+    Linear,        // -6 dB at the centre, sums flat in mono
+    ConstantPower, // -3 dB at the centre, constant loudness in stereo
+    Compromise,    // -4.5 dB at the centre
+}
+
+impl PanLaw {
+    // (left, right) gains for pan in [-1, 1]
+    pub fn gains(&self, pan: f32) -> (f32, f32) {
+        let pan = math::clamp(pan, -1., 1.);
+        match self {
+            PanLaw::Linear => ((1. - pan) * 0.5, (1. + pan) * 0.5),
+            PanLaw::ConstantPower => math::pan_gains(pan),
+            PanLaw::Compromise => {
+                let (left, right) = math::pan_gains(pan);
+                (math::sqrtf(left * (1. - pan) * 0.5), math::sqrtf(right * (1. + pan) * 0.5))
+            }
+        }
+    }
+}
+
+
+// - mixer::mid / side --------------------------------------------------------
+
+// Converts a stereo buffer to mid / side in place, mid in channel 0 and
+// side in channel 1.
+pub fn encode_ms(num_channels: usize, buffer: &mut Buffer) {
+    if num_channels < 2 {
+        return;
+    }
+    for frame in buffer.chunks_mut(num_channels) {
+        let (right, left) = (frame[0], frame[1]);
+        frame[0] = (left + right) * 0.5;
+        frame[1] = (left - right) * 0.5;
+    }
+}
+
+// Converts a mid / side buffer back to stereo in place.
+pub fn decode_ms(num_channels: usize, buffer: &mut Buffer) {
+    if num_channels < 2 {
+        return;
+    }
+    for frame in buffer.chunks_mut(num_channels) {
+        let (mid, side) = (frame[0], frame[1]);
+        frame[0] = mid - side;
+        frame[1] = mid + side;
+    }
+}
+
+// Scales the side signal of a stereo buffer: 0 is mono, 1 leaves the
+// image unchanged and 2 doubles its width.
+pub fn set_width(num_channels: usize, buffer: &mut Buffer, width: f32) {
+    if num_channels < 2 {
+        return;
+    }
+    for frame in buffer.chunks_mut(num_channels) {
+        let (right, left) = (frame[0], frame[1]);
+        let mid = (left + right) * 0.5;
+        let side = (left - right) * 0.5 * width;
+        frame[0] = mid - side;
+        frame[1] = mid + side;
+    }
+}
+
+
+// - mixer::Strip -------------------------------------------------------------
+
+// Input strip. Sources render or copy their audio into the strip's stereo
+// input buffer, a `mono` strip only uses channel 0 and is placed with the
+// mixer's pan law, a stereo strip's pan sets the balance.
+
+pub struct Strip {
+    pub gain: f32, // dB
+    pub pan: f32,  // -1 left, 1 right
+    pub mute: bool,
+    pub solo: bool,
+    pub mono: bool,
+    pub pre_fader: bool, // sends are taken before the gain
+    pub sends: Vec<f32>, // 0 - 1, level sent to each bus
+
+    input: Vec<f32>,
+    gains: Vec<smooth::Linear>, // left, right, then left, right per bus
+}
+
+
+impl Strip {
+    fn new(block_length: usize, num_buses: usize) -> Strip {
+        Strip {
+            gain: 0.,
+            pan: 0.,
+            mute: false,
+            solo: false,
+            mono: false,
+            pre_fader: false,
+            sends: vec![0.; num_buses],
+            input: vec![0.; block_length * NUM_CHANNELS],
+            gains: vec![smooth::Linear::new(block_length, 0.); NUM_CHANNELS * (num_buses + 1)],
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    pub fn set_pan(&mut self, pan: f32) {
+        self.pan = math::clamp(pan, -1., 1.);
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        self.mute = mute;
+    }
+
+    pub fn set_solo(&mut self, solo: bool) {
+        self.solo = solo;
+    }
+
+    pub fn set_mono(&mut self, mono: bool) {
+        self.mono = mono;
+    }
+
+    pub fn set_pre_fader(&mut self, pre_fader: bool) {
+        self.pre_fader = pre_fader;
+    }
+
+    pub fn set_send(&mut self, bus: usize, level: f32) {
+        if let Some(send) = self.sends.get_mut(bus) {
+            *send = math::clamp(level, 0., 1.);
+        }
+    }
+
+    // stereo input buffer for the current block
+    pub fn input(&mut self) -> &mut Buffer {
+        &mut self.input
+    }
+
+    // ramp the gains to their new values over the next block
+    fn update(&mut self, pan_law: PanLaw, audible: bool) {
+        let (left, right) = if !audible {
+            (0., 0.)
+        } else if self.mono {
+            pan_law.gains(self.pan)
+        } else {
+            (1f32.min(1. - self.pan), 1f32.min(1. + self.pan))
+        };
+        let fader = math::db_to_gain(self.gain);
+        let send_fader = if self.pre_fader { 1. } else { fader };
+
+        self.gains[0].set_target(left * fader);
+        self.gains[1].set_target(right * fader);
+        for (bus, send) in self.sends.iter().enumerate() {
+            let index = NUM_CHANNELS * (bus + 1);
+            self.gains[index].set_target(left * send * send_fader);
+            self.gains[index + 1].set_target(right * send * send_fader);
+        }
+    }
+}
+
+
+// - mixer::Bus ---------------------------------------------------------------
+
+// Effect bus fed by the strip sends, its return is mixed into the master.
+
+pub struct Bus {
+    pub gain: f32, // dB, return level
+    pub mute: bool,
+
+    buffer: Vec<f32>,
+    level: smooth::Linear,
+}
+
+
+impl Bus {
+    fn new(block_length: usize) -> Bus {
+        Bus {
+            gain: 0.,
+            mute: false,
+            buffer: vec![0.; block_length * NUM_CHANNELS],
+            level: smooth::Linear::new(block_length, 1.),
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        self.mute = mute;
+    }
+}
+
+
+// - mixer::Mixer -------------------------------------------------------------
+
+// Mixes input strips, effect buses and a master bus into an interleaved
+// output buffer.
+//
+// For each block render the sources into the strip inputs, then call
+// `mix` with the output buffer and a closure that runs the effect on each
+// bus, e.g.
+//
+//     voices.render(2, mixer.strips[0].input());
+//     let input = mixer.strips[1].input(); // live input
+//     let length = input.len().min(buffer.len());
+//     input[..length].copy_from_slice(&buffer[..length]);
+//     mixer.mix(2, buffer, |bus, buffer| match bus {
+//         0 => reverb.process(2, buffer),
+//         _ => delay.process(2, buffer),
+//     });
+//
+// A block is at most `block_length` frames, output frames past that are
+// cleared. Strip inputs are cleared after each mix. When any strip is soloed only
+// soloed strips are heard, sends included. Gain changes are ramped over a
+// block.
+
+pub struct Mixer {
+    pub fs: f32,
+    pub pan_law: PanLaw,
+    pub gain: f32,  // dB, master gain
+    pub width: f32, // 0 - 2, master stereo width
+    pub mute: bool,
+
+    pub strips: Vec<Strip>,
+    pub buses: Vec<Bus>,
+
+    block_length: usize,
+    master: smooth::Linear,
+}
+
+
+impl Mixer {
+    pub fn new(fs: f32, block_length: usize, num_strips: usize, num_buses: usize) -> Mixer {
+        Mixer {
+            fs: fs,
+            pan_law: PanLaw::ConstantPower,
+            gain: 0.,
+            width: 1.,
+            mute: false,
+            strips: (0..num_strips).map(|_| Strip::new(block_length, num_buses)).collect(),
+            buses: (0..num_buses).map(|_| Bus::new(block_length)).collect(),
+            block_length: block_length,
+            master: smooth::Linear::new(block_length, 1.),
+        }
+    }
+
+    pub fn set_pan_law(&mut self, pan_law: PanLaw) {
+        self.pan_law = pan_law;
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width = math::clamp(width, 0., 2.);
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        self.mute = mute;
+    }
+
+    // maximum frames in a block
+    pub fn block_length(&self) -> usize {
+        self.block_length
+    }
+
+    // Mixes the strip inputs into `output`, an interleaved buffer with
+    // `num_channels` channels and at most `block_length` frames. `effect`
+    // is called with the index and stereo buffer of each bus once the
+    // sends have been mixed in.
+    pub fn mix<F: FnMut(usize, &mut Buffer)>(&mut self, num_channels: usize, output: &mut Buffer, mut effect: F) {
+        if num_channels == 0 {
+            return;
+        }
+        let frames = (output.len() / num_channels).min(self.block_length);
+        let length = frames * NUM_CHANNELS;
+
+        let soloed = self.strips.iter().any(|strip| strip.solo);
+        for strip in self.strips.iter_mut() {
+            let audible = !strip.mute && (!soloed || strip.solo);
+            strip.update(self.pan_law, audible);
+        }
+        for bus in self.buses.iter_mut() {
+            let level = if bus.mute { 0. } else { math::db_to_gain(bus.gain) };
+            bus.level.set_target(level);
+            for x in bus.buffer[..length].iter_mut() {
+                *x = 0.;
+            }
+        }
+        let level = if self.mute { 0. } else { math::db_to_gain(self.gain) };
+        self.master.set_target(level);
+
+        // strips into the master and the bus sends
+        let mut master = [0.; NUM_CHANNELS];
+        for x in output.iter_mut() {
+            *x = 0.;
+        }
+        for strip in self.strips.iter_mut() {
+            let mono = strip.mono;
+            for index in 0..frames {
+                let base = index * NUM_CHANNELS;
+                let right = strip.input[base];
+                let left = if mono { right } else { strip.input[base + 1] };
+                let gain_left = strip.gains[0].next();
+                let gain_right = strip.gains[1].next();
+                master[0] = right * gain_right;
+                master[1] = left * gain_left;
+                add_frame(&mut output[index * num_channels..(index + 1) * num_channels], &master);
+                for (bus, gains) in self.buses.iter_mut().zip(strip.gains[NUM_CHANNELS..].chunks_mut(NUM_CHANNELS)) {
+                    let gain_left = gains[0].next();
+                    let gain_right = gains[1].next();
+                    bus.buffer[base] += right * gain_right;
+                    bus.buffer[base + 1] += left * gain_left;
+                }
+            }
+            for x in strip.input.iter_mut() {
+                *x = 0.;
+            }
+        }
+
+        // bus effects and returns
+        for (index, bus) in self.buses.iter_mut().enumerate() {
+            effect(index, &mut bus.buffer[..length]);
+            for (frame, x) in bus.buffer[..length].chunks(NUM_CHANNELS).enumerate() {
+                let level = bus.level.next();
+                master[0] = x[0] * level;
+                master[1] = x[1] * level;
+                add_frame(&mut output[frame * num_channels..(frame + 1) * num_channels], &master);
+            }
+        }
+
+        // master bus
+        if num_channels >= NUM_CHANNELS && self.width != 1. {
+            set_width(num_channels, &mut output[..frames * num_channels], self.width);
+        }
+        for frame in output.chunks_mut(num_channels).take(frames) {
+            let level = self.master.next();
+            for x in frame.iter_mut() {
+                *x *= level;
+            }
+        }
+    }
+}
+
+
+// mix a stereo frame into a frame with any number of channels
+#[inline(always)]
+fn add_frame(frame: &mut [f32], x: &[f32; NUM_CHANNELS]) {
+    if frame.len() == 1 {
+        frame[0] += (x[0] + x[1]) * 0.5;
+    } else {
+        frame[0] += x[0];
+        frame[1] += x[1];
+    }
+}
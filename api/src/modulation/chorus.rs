@@ -0,0 +1,66 @@
+use crate::audio::Buffer;
+use crate::lfo::Shape;
+use crate::math;
+use crate::modulation::ModulatedDelay;
+
+
+// - global constants ---------------------------------------------------------
+
+const MAX_TIME: f32 = 0.05; // seconds
+
+
+// - modulation::Chorus -------------------------------------------------------
+
+pub struct Chorus {
+    pub core: ModulatedDelay,
+}
+
+
+impl Chorus {
+    pub fn new(fs: f32) -> Chorus {
+        let mut core = ModulatedDelay::new(fs, MAX_TIME, Shape::Triangle);
+        core.delay = 0.015;
+        core.depth = 0.003;
+        core.feedback = 0.;
+        core.mix = 0.5;
+        core.set_rate(0.8);
+        core.set_spread(0.25);
+        Chorus {
+            core: core,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.core.set_rate(math::clamp(rate, 0.01, 10.));
+    }
+
+    // seconds
+    pub fn set_delay(&mut self, delay: f32) {
+        self.core.delay = math::clamp(delay, 0.005, 0.03);
+    }
+
+    // seconds
+    pub fn set_depth(&mut self, depth: f32) {
+        self.core.depth = math::clamp(depth, 0., 0.01);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.core.feedback = math::clamp(feedback, 0., 0.7);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.core.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.core.set_spread(spread);
+    }
+
+    pub fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        self.core.process(num_channels, buffer);
+    }
+}
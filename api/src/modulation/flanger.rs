@@ -0,0 +1,69 @@
+use crate::audio::Buffer;
+use crate::lfo::Shape;
+use crate::math;
+use crate::modulation::ModulatedDelay;
+
+
+// - global constants ---------------------------------------------------------
+
+const MAX_TIME: f32 = 0.02; // seconds
+
+
+// - modulation::Flanger ------------------------------------------------------
+
+// Short modulated delay with feedback. Negative feedback gives the hollow
+// odd-harmonic flavour, positive the more metallic one.
+
+pub struct Flanger {
+    pub core: ModulatedDelay,
+}
+
+
+impl Flanger {
+    pub fn new(fs: f32) -> Flanger {
+        let mut core = ModulatedDelay::new(fs, MAX_TIME, Shape::Sine);
+        core.delay = 0.003;
+        core.depth = 0.002;
+        core.feedback = 0.6;
+        core.mix = 0.5;
+        core.set_rate(0.2);
+        core.set_spread(0.);
+        Flanger {
+            core: core,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.core.set_rate(math::clamp(rate, 0.01, 10.));
+    }
+
+    // seconds
+    pub fn set_delay(&mut self, delay: f32) {
+        self.core.delay = math::clamp(delay, 0.0005, 0.01);
+    }
+
+    // seconds
+    pub fn set_depth(&mut self, depth: f32) {
+        self.core.depth = math::clamp(depth, 0., 0.005);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.core.feedback = math::clamp(feedback, -0.95, 0.95);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.core.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.core.set_spread(spread);
+    }
+
+    pub fn reset(&mut self) {
+        self.core.reset();
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        self.core.process(num_channels, buffer);
+    }
+}
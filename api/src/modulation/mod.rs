@@ -0,0 +1,109 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::delay::DelayLine;
+use crate::lfo::{Lfo, Rate, Shape};
+use crate::math;
+
+
+// - modules ------------------------------------------------------------------
+
+pub mod chorus;
+pub mod flanger;
+pub mod phaser;
+
+
+// - exports ------------------------------------------------------------------
+
+pub use chorus::Chorus;
+pub use flanger::Flanger;
+pub use phaser::Phaser;
+
+
+// - modulation::ModulatedDelay -----------------------------------------------
+
+// Stereo delay whose read taps are swept by an lfo, the common core of
+// chorus and flanger. The lfo for the second channel is offset by
+// `spread` cycles to widen the stereo image.
+
+pub struct ModulatedDelay {
+    pub fs: f32,
+    pub delay: f32,    // seconds, centre of the sweep
+    pub depth: f32,    // seconds, sweep either side of delay
+    pub feedback: f32, // -1 - 1
+    pub mix: f32,      // 0 - 1, dry / wet
+    pub spread: f32,   // 0 - 0.5, lfo phase offset between channels
+
+    lines: [DelayLine<Vec<f32>>; 2],
+    lfos: [Lfo; 2],
+}
+
+
+impl ModulatedDelay {
+    pub fn new(fs: f32, max_time: f32, shape: Shape) -> ModulatedDelay {
+        let length = (max_time * fs) as usize + 4;
+        let mut lfos = [Lfo::new(shape, fs, Rate::Hz(0.5)), Lfo::new(shape, fs, Rate::Hz(0.5))];
+        lfos[1].set_phase_offset(0.25);
+        ModulatedDelay {
+            fs: fs,
+            delay: max_time * 0.5,
+            depth: max_time * 0.25,
+            feedback: 0.,
+            mix: 0.5,
+            spread: 0.25,
+            lines: [DelayLine::new(length), DelayLine::new(length)],
+            lfos: lfos,
+        }
+    }
+
+    pub fn max_time(&self) -> f32 {
+        (self.lines[0].len() - 4) as f32 / self.fs
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_rate(Rate::Hz(rate));
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_shape(shape);
+        }
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.spread = math::clamp(spread, 0., 0.5);
+        self.lfos[1].set_phase_offset(self.spread);
+    }
+
+    pub fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.clear();
+        }
+        for lfo in self.lfos.iter_mut() {
+            lfo.trigger();
+        }
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let max = self.max_time() * self.fs;
+        let centre = self.delay * self.fs;
+        let depth = self.depth * self.fs;
+        let dry = 1. - self.mix;
+        let wet = self.mix;
+        for frame in buffer.chunks_mut(num_channels) {
+            for channel in 0..num_channels.min(2) {
+                let x = frame[channel];
+                let delay = math::clamp(centre + depth * self.lfos[channel].next(), 1., max);
+                let y = self.lines[channel].read_cubic(delay);
+                self.lines[channel].write(x + y * self.feedback);
+                frame[channel] = x * dry + y * wet;
+            }
+        }
+    }
+}
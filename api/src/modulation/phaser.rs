@@ -0,0 +1,136 @@
+use crate::audio::Buffer;
+use crate::lfo::{Lfo, Rate, Shape};
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MAX_STAGES: usize = 12;
+
+// allpass coefficients are recomputed every CONTROL_INTERVAL frames
+const CONTROL_INTERVAL: usize = 8;
+
+
+// - modulation::Phaser -------------------------------------------------------
+
+// Chain of first order allpass filters whose break frequency is swept
+// exponentially between `minimum` and `maximum` by an lfo. The notches
+// come from mixing the phase shifted signal back with the dry input.
+
+pub struct Phaser {
+    pub fs: f32,
+    pub minimum: f32,   // Hz
+    pub maximum: f32,   // Hz
+    pub feedback: f32,  // -1 - 1
+    pub mix: f32,       // 0 - 1, dry / wet
+    pub spread: f32,    // 0 - 0.5, lfo phase offset between channels
+
+    stages: usize, // 2 - MAX_STAGES
+    lfos: [Lfo; 2],
+    coefficients: [f32; 2],
+    state: [[f32; MAX_STAGES]; 2],
+    last: [f32; 2],
+    counter: usize,
+}
+
+
+impl Phaser {
+    pub fn new(fs: f32) -> Phaser {
+        let mut lfos = [Lfo::new(Shape::Sine, fs, Rate::Hz(0.3)), Lfo::new(Shape::Sine, fs, Rate::Hz(0.3))];
+        lfos[1].set_phase_offset(0.25);
+        Phaser {
+            fs: fs,
+            minimum: 200.,
+            maximum: 4000.,
+            feedback: 0.5,
+            mix: 0.5,
+            spread: 0.25,
+            stages: 6,
+            lfos: lfos,
+            coefficients: [0.; 2],
+            state: [[0.; MAX_STAGES]; 2],
+            last: [0.; 2],
+            counter: 0,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_rate(Rate::Hz(math::clamp(rate, 0.01, 10.)));
+        }
+    }
+
+    pub fn set_stages(&mut self, stages: usize) {
+        self.stages = if stages < 2 { 2 } else if stages > MAX_STAGES { MAX_STAGES } else { stages };
+    }
+
+    pub fn stages(&self) -> usize {
+        self.stages
+    }
+
+    pub fn set_range(&mut self, minimum: f32, maximum: f32) {
+        let nyquist = self.fs * 0.45;
+        self.minimum = math::clamp(minimum, 20., nyquist);
+        self.maximum = math::clamp(maximum, self.minimum, nyquist);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = math::clamp(feedback, -0.95, 0.95);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.spread = math::clamp(spread, 0., 0.5);
+        self.lfos[1].set_phase_offset(self.spread);
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [[0.; MAX_STAGES]; 2];
+        self.last = [0.; 2];
+        self.counter = 0;
+        for lfo in self.lfos.iter_mut() {
+            lfo.trigger();
+        }
+    }
+
+    fn update(&mut self, channel: usize) {
+        // lfo in [-1, 1] -> [0, 1] -> exponential sweep
+        let position = (self.lfos[channel].advance(CONTROL_INTERVAL) + 1.) * 0.5;
+        let frequency = self.minimum * math::powf(self.maximum / self.minimum, position);
+        let t = math::tanf(math::PI * frequency / self.fs);
+        self.coefficients[channel] = (t - 1.) / (t + 1.);
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let dry = 1. - self.mix;
+        let wet = self.mix;
+        for frame in buffer.chunks_mut(num_channels) {
+            if self.counter == 0 {
+                for channel in 0..num_channels.min(2) {
+                    self.update(channel);
+                }
+                self.counter = CONTROL_INTERVAL;
+            }
+            self.counter -= 1;
+
+            for channel in 0..num_channels.min(2) {
+                let x = frame[channel];
+                let a = self.coefficients[channel];
+                let mut y = x + self.last[channel] * self.feedback;
+                for z in self.state[channel][..self.stages].iter_mut() {
+                    let output = a * y + *z;
+                    *z = y - a * output;
+                    y = output;
+                }
+                self.last[channel] = y;
+                frame[channel] = x * dry + y * wet;
+            }
+        }
+    }
+}
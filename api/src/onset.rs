@@ -0,0 +1,486 @@
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf::EspError;
+
+use crate::audio::Buffer;
+use crate::fft::{Complex, Fft, TapReader, Window};
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MAX_EVENTS: usize = 32;
+
+const AVERAGE_LENGTH: usize = 16;    // flux values in the adaptive threshold
+const HISTORY_LENGTH: usize = 1024;  // flux values kept for tempo estimation
+const COMPRESSION: f32 = 100.;       // log(1 + C * |X|) magnitude compression
+
+
+// - onset::Event -------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Event {
+    Onset { time: u64, strength: f32 }, // strength is the spectral flux
+    Beat { time: u64, number: u64 },    // counted from 0 when tracking starts
+}
+
+impl Event {
+    // frames since the tracker was created or reset
+    pub fn time(&self) -> u64 {
+        match self {
+            Event::Onset { time, .. } => *time,
+            Event::Beat { time, .. } => *time,
+        }
+    }
+}
+
+
+// - onset::Detector ----------------------------------------------------------
+
+// Spectral flux onset detector.
+//
+// Each frame of `size` samples is windowed and transformed, the flux is
+// the summed increase in log compressed magnitude over the previous frame.
+// An onset is a peak in the flux above `threshold` times its recent
+// average plus `delta`, at least `min_interval` seconds after the last.
+// Peaks are picked one frame late.
+//
+// See: Dixon, "Onset detection revisited", 2006
+
+pub struct Detector {
+    pub fs: f32,
+    pub threshold: f32,    // multiple of the local average flux
+    pub delta: f32,        // minimum flux above the average
+    pub min_interval: f32, // seconds
+
+    fft: Fft,
+    hop: usize,
+    window: Vec<f32>,
+    frame: Vec<f32>,
+    bins: Vec<Complex>,
+    previous: Vec<f32>,
+    average: [f32; AVERAGE_LENGTH],
+    index: usize,
+    flux: [f32; 2], // flux of the last two frames, newest first
+    holdoff: usize,
+}
+
+
+impl Detector {
+    pub fn new(fs: f32, size: usize, hop: usize) -> Result<Detector, EspError> {
+        let fft = Fft::new(size)?;
+        let bins = fft.bins();
+        let mut window = vec![0.; size];
+        Window::Hann.fill(&mut window);
+        Ok(Detector {
+            fs: fs,
+            threshold: 1.5,
+            delta: 1.,
+            min_interval: 0.05,
+            fft: fft,
+            hop: hop.max(1).min(size),
+            window: window,
+            frame: vec![0.; size],
+            bins: vec![Complex::default(); bins],
+            previous: vec![0.; bins],
+            average: [0.; AVERAGE_LENGTH],
+            index: 0,
+            flux: [0.; 2],
+            holdoff: 0,
+        })
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold.max(1.);
+    }
+
+    pub fn set_delta(&mut self, delta: f32) {
+        self.delta = delta.max(0.);
+    }
+
+    pub fn set_min_interval(&mut self, min_interval: f32) {
+        self.min_interval = min_interval.max(0.);
+    }
+
+    pub fn size(&self) -> usize {
+        self.fft.size()
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.previous.iter_mut() {
+            *x = 0.;
+        }
+        self.average = [0.; AVERAGE_LENGTH];
+        self.flux = [0.; 2];
+        self.holdoff = 0;
+    }
+
+    // Analyzes the next frame of `size()` samples, `hop()` samples on from
+    // the last one. Returns the flux of this frame and the strength of an
+    // onset in the previous frame, if there was one.
+    pub fn analyze(&mut self, input: &[f32]) -> (f32, Option<f32>) {
+        for ((y, x), w) in self.frame.iter_mut().zip(input).zip(&self.window) {
+            *y = x * w;
+        }
+        self.fft.forward(&self.frame, &mut self.bins);
+
+        let mut flux = 0.;
+        for (previous, x) in self.previous.iter_mut().zip(&self.bins) {
+            let magnitude = math::logf(1. + COMPRESSION * x.norm());
+            flux += (magnitude - *previous).max(0.);
+            *previous = magnitude;
+        }
+
+        // the previous frame is a peak above the local average
+        let candidate = self.flux[0];
+        let average = self.average.iter().sum::<f32>() / AVERAGE_LENGTH as f32;
+        let onset = candidate > self.flux[1] && candidate >= flux
+            && candidate > average * self.threshold + self.delta
+            && self.holdoff == 0;
+
+        self.average[self.index] = candidate;
+        self.index = (self.index + 1) % AVERAGE_LENGTH;
+        self.flux = [flux, candidate];
+        if self.holdoff > 0 {
+            self.holdoff -= 1;
+        }
+        if onset {
+            self.holdoff = (self.min_interval * self.fs / self.hop as f32) as usize;
+            (flux, Some(candidate))
+        } else {
+            (flux, None)
+        }
+    }
+}
+
+
+// - onset::Tempo -------------------------------------------------------------
+
+// Tempo estimator and beat tracker over an onset detection function, e.g.
+// the flux from `Detector`, sampled at `rate` values per second.
+//
+// The tempo is the strongest autocorrelation lag of the last few seconds
+// of flux between `min_tempo` and `max_tempo`, weighted towards 120 bpm to
+// avoid locking to half or double time. Beats are predicted from the last
+// one and pulled towards onsets that land close to the prediction.
+
+pub struct Tempo {
+    pub rate: f32,
+    pub min_tempo: f32, // bpm
+    pub max_tempo: f32, // bpm
+    pub tracking: f32,  // 0 - 1, how far an onset pulls the beat
+
+    history: Vec<f32>,
+    index: usize,
+    filled: usize,
+    correlation: Vec<f32>,
+    countdown: usize,
+    period: f32,     // flux values per beat, 0 until known
+    until_beat: f32, // flux values until the next beat
+    beats: u64,
+}
+
+
+impl Tempo {
+    pub fn new(rate: f32) -> Tempo {
+        Tempo {
+            rate: rate,
+            min_tempo: 60.,
+            max_tempo: 200.,
+            tracking: 0.25,
+            history: vec![0.; HISTORY_LENGTH],
+            index: 0,
+            filled: 0,
+            correlation: vec![0.; HISTORY_LENGTH / 2 + 1],
+            countdown: 0,
+            period: 0.,
+            until_beat: 0.,
+            beats: 0,
+        }
+    }
+
+    pub fn set_range(&mut self, min_tempo: f32, max_tempo: f32) {
+        self.min_tempo = math::clamp(min_tempo, 30., 300.);
+        self.max_tempo = math::clamp(max_tempo, self.min_tempo + 1., 300.);
+    }
+
+    pub fn set_tracking(&mut self, tracking: f32) {
+        self.tracking = math::clamp(tracking, 0., 1.);
+    }
+
+    // estimated tempo in bpm
+    pub fn tempo(&self) -> Option<f32> {
+        if self.period > 0. { Some(60. * self.rate / self.period) } else { None }
+    }
+
+    // Position in beats, beat 0 is the first tracked beat. Pass to
+    // `Lfo::sync` to lock tempo synced modulation to the beat.
+    pub fn beat(&self) -> f32 {
+        if self.period > 0. {
+            self.beats as f32 - self.until_beat / self.period
+        } else {
+            0.
+        }
+    }
+
+    // number of beats tracked so far
+    pub fn beats(&self) -> u64 {
+        self.beats
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.history.iter_mut() {
+            *x = 0.;
+        }
+        self.index = 0;
+        self.filled = 0;
+        self.countdown = 0;
+        self.period = 0.;
+        self.until_beat = 0.;
+        self.beats = 0;
+    }
+
+    // Adds the next value of the detection function. Returns the offset of
+    // a beat falling within this step, 0 - 1 in steps before now.
+    pub fn push(&mut self, flux: f32) -> Option<f32> {
+        self.history[self.index] = flux;
+        self.index = (self.index + 1) % HISTORY_LENGTH;
+        self.filled = (self.filled + 1).min(HISTORY_LENGTH);
+
+        if self.countdown == 0 {
+            self.estimate();
+            self.countdown = (self.rate * 0.5) as usize;
+        } else {
+            self.countdown -= 1;
+        }
+
+        if self.period <= 0. {
+            return None;
+        }
+        self.until_beat -= 1.;
+        if self.until_beat <= 0. {
+            let offset = -self.until_beat;
+            self.until_beat += self.period;
+            self.beats += 1;
+            return Some(offset);
+        }
+        None
+    }
+
+    // Pulls the beat grid towards an onset `age` steps before now.
+    pub fn onset(&mut self, age: f32) {
+        if self.period <= 0. {
+            return;
+        }
+        let to_next = self.until_beat + age;
+        let from_last = self.period - to_next;
+        let window = self.period * 0.2;
+        if from_last >= 0. && from_last < window && from_last < to_next {
+            self.until_beat += from_last * self.tracking;
+        } else if to_next < window {
+            self.until_beat -= to_next * self.tracking;
+        }
+    }
+
+    fn estimate(&mut self) {
+        // wait for at least three seconds of flux
+        let length = self.filled;
+        if (length as f32) < self.rate * 3. {
+            return;
+        }
+        let lag_min = ((60. * self.rate / self.max_tempo) as usize).max(1);
+        let lag_max = ((60. * self.rate / self.min_tempo) as usize + 1).min(length / 2 - 1);
+        if lag_min + 2 > lag_max {
+            return;
+        }
+
+        let start = (self.index + HISTORY_LENGTH - length) % HISTORY_LENGTH;
+        let history = &self.history;
+        let mean = (0..length).map(|n| history[(start + n) % HISTORY_LENGTH]).sum::<f32>() / length as f32;
+        let at = |n: usize| history[(start + n) % HISTORY_LENGTH] - mean;
+
+        // autocorrelation with a log gaussian weight around 120 bpm
+        let mut best = 0;
+        for lag in lag_min - 1..=lag_max + 1 {
+            let mut sum = 0.;
+            for n in 0..length - lag {
+                sum += at(n) * at(n + lag);
+            }
+            let octaves = math::log2f(60. * self.rate / (lag as f32 * 120.));
+            let weight = math::expf(-0.5 * octaves * octaves);
+            self.correlation[lag] = sum / (length - lag) as f32 * weight;
+            if lag >= lag_min && lag <= lag_max && (best == 0 || self.correlation[lag] > self.correlation[best]) {
+                best = lag;
+            }
+        }
+        if self.correlation[best] <= 0. {
+            return;
+        }
+
+        let (a, b, c) = (self.correlation[best - 1], self.correlation[best], self.correlation[best + 1]);
+        let denominator = a - 2. * b + c;
+        let offset = if denominator < 0. { math::clamp(0.5 * (a - c) / denominator, -0.5, 0.5) } else { 0. };
+        let period = best as f32 + offset;
+
+        // smooth small changes, lock on to the phase of new tempos
+        if self.period > 0. && math::fabsf(period - self.period) < self.period * 0.1 {
+            self.period += (period - self.period) * 0.5;
+        } else {
+            self.period = period;
+            self.until_beat = period - self.phase(period, length);
+        }
+    }
+
+    // Steps since the last beat on the grid of `period` that lines up best
+    // with the flux history.
+    fn phase(&self, period: f32, length: usize) -> f32 {
+        let last = (self.index + HISTORY_LENGTH - 1) % HISTORY_LENGTH;
+        let mut best = 0;
+        let mut best_score = 0.;
+        for phase in 0..period as usize {
+            let mut score = 0.;
+            let mut age = phase as f32;
+            while (age as usize) < length {
+                score += self.history[(last + HISTORY_LENGTH - age as usize) % HISTORY_LENGTH];
+                age += period;
+            }
+            if score > best_score {
+                best = phase;
+                best_score = score;
+            }
+        }
+        best as f32
+    }
+}
+
+
+// - onset::Tracker -----------------------------------------------------------
+
+// Onset detection and beat tracking over input audio, e.g. a live drummer.
+//
+// Feed input buffers with `process` or drain a `fft::tap` with `update`,
+// then pop the timestamped onset and beat events. `tempo` and `beat` can
+// drive tempo synced delays and LFOs.
+
+pub struct Tracker {
+    pub detector: Detector,
+    pub tempo: Tempo,
+
+    input: Vec<f32>,
+    count: usize,
+    time: u64, // frame at the start of `input`
+    events: VecDeque<Event>,
+}
+
+
+impl Tracker {
+    pub fn new(fs: f32) -> Result<Tracker, EspError> {
+        let detector = Detector::new(fs, 1024, 256)?;
+        let rate = fs / detector.hop() as f32;
+        Ok(Tracker {
+            input: vec![0.; detector.size()],
+            detector: detector,
+            tempo: Tempo::new(rate),
+            count: 0,
+            time: 0,
+            events: VecDeque::with_capacity(MAX_EVENTS),
+        })
+    }
+
+    // estimated tempo in bpm
+    pub fn tempo(&self) -> Option<f32> {
+        self.tempo.tempo()
+    }
+
+    // position in beats, see `Tempo::beat`
+    pub fn beat(&self) -> f32 {
+        self.tempo.beat()
+    }
+
+    // oldest pending event
+    pub fn pop(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    pub fn reset(&mut self) {
+        self.detector.reset();
+        self.tempo.reset();
+        self.count = 0;
+        self.time = 0;
+        self.events.clear();
+    }
+
+    // Feeds an interleaved buffer with `num_channels` channels, returns true
+    // if there are new events.
+    pub fn process(&mut self, num_channels: usize, buffer: &Buffer) -> bool {
+        if num_channels == 0 {
+            return false;
+        }
+        let scale = 1. / num_channels as f32;
+        let mut updated = false;
+        for frame in buffer.chunks(num_channels) {
+            let x: f32 = frame.iter().sum();
+            self.input[self.count] = x * scale;
+            self.count += 1;
+            if self.count == self.input.len() {
+                updated |= self.advance();
+            }
+        }
+        updated
+    }
+
+    // Drains a tap, returns true if there are new events.
+    pub fn update(&mut self, reader: &mut TapReader) -> bool {
+        let mut updated = false;
+        loop {
+            self.count += reader.pop(&mut self.input[self.count..]);
+            if self.count < self.input.len() {
+                break;
+            }
+            updated |= self.advance();
+        }
+        updated
+    }
+
+    fn advance(&mut self) -> bool {
+        let hop = self.detector.hop();
+        let size = self.input.len();
+        let mut updated = false;
+
+        // new audio enters at the end of the frame, an onset is timed from
+        // the middle of the newest hop of the previous frame
+        let (flux, onset) = self.detector.analyze(&self.input);
+        if let Some(strength) = onset {
+            let time = (self.time + (size - hop / 2) as u64).saturating_sub(hop as u64);
+            self.emit(Event::Onset { time: time, strength: strength });
+            self.tempo.onset(0.);
+            updated = true;
+        }
+        if let Some(offset) = self.tempo.push(flux) {
+            let age = (offset * hop as f32) as u64;
+            let time = (self.time + (size - hop / 2) as u64).saturating_sub(age);
+            self.emit(Event::Beat { time: time, number: self.tempo.beats() - 1 });
+            updated = true;
+        }
+
+        self.input.copy_within(hop.., 0);
+        self.count = size - hop;
+        self.time += hop as u64;
+        updated
+    }
+
+    // the oldest event is dropped when the queue is full
+    fn emit(&mut self, event: Event) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
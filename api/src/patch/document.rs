@@ -0,0 +1,316 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::logger;
+
+
+// - global constants ---------------------------------------------------------
+
+const TAG: &str = "api::patch";
+
+
+// - patch::Value -------------------------------------------------------------
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Number(f32),
+    Boolean(bool),
+    Text(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn number(&self) -> Result<f32, EspError> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+
+    pub fn boolean(&self) -> Result<bool, EspError> {
+        match self {
+            Value::Boolean(boolean) => Ok(*boolean),
+            _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+
+    pub fn text(&self) -> Result<&str, EspError> {
+        match self {
+            Value::Text(text) => Ok(text),
+            _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+
+    pub fn list(&self) -> Result<&[Value], EspError> {
+        match self {
+            Value::List(list) => Ok(list),
+            _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+}
+
+
+// - patch::Table -------------------------------------------------------------
+
+// Key / value pairs in the order they were written.
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Table {
+    pub entries: Vec<(String, Value)>,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    // value for a key that must be present
+    pub fn require(&self, key: &str) -> Result<&Value, EspError> {
+        self.get(key).ok_or_else(|| idf::ESP_ERR_NOT_FOUND.into())
+    }
+
+    pub fn number_or(&self, key: &str, default: f32) -> Result<f32, EspError> {
+        match self.get(key) {
+            Some(value) => value.number(),
+            None => Ok(default),
+        }
+    }
+
+    pub fn text_or<'a>(&'a self, key: &str, default: &'a str) -> Result<&'a str, EspError> {
+        match self.get(key) {
+            Some(value) => value.text(),
+            None => Ok(default),
+        }
+    }
+}
+
+
+// - patch::Document ----------------------------------------------------------
+
+// A parsed patch file: the keys before the first header and each `[name]`
+// or `[[name]]` section in order. Both kinds of header simply start a new
+// section, repeated `[[node]]` headers give one section per node.
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Document {
+    pub root: Table,
+    pub sections: Vec<(String, Table)>,
+}
+
+impl Document {
+    // Parses the subset of TOML used by patches: bare or quoted keys,
+    // numbers, booleans, basic strings, arrays and `#` comments. Dotted
+    // keys, inline tables and dates are not supported.
+    pub fn parse(text: &str) -> Result<Document, EspError> {
+        let mut parser = Parser { text: text, bytes: text.as_bytes(), index: 0, line: 1 };
+        parser.document().map_err(|e| {
+            log!(TAG, "parse error on line {}: {:?}", parser.line, e);
+            e
+        })
+    }
+
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Table> + 'a {
+        self.sections.iter().filter(move |(section, _)| section == name).map(|(_, table)| table)
+    }
+}
+
+
+// - parser -------------------------------------------------------------------
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    index: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn document(&mut self) -> Result<Document, EspError> {
+        let mut document = Document::default();
+        loop {
+            self.skip_blank(true);
+            match self.peek() {
+                None => return Ok(document),
+                Some(b'[') => {
+                    self.index += 1;
+                    let array = self.eat(b'[');
+                    self.skip_blank(false);
+                    let name = self.key()?;
+                    self.skip_blank(false);
+                    self.expect(b']')?;
+                    if array {
+                        self.expect(b']')?;
+                    }
+                    self.end_of_line()?;
+                    document.sections.push((name, Table::default()));
+                }
+                Some(_) => {
+                    let table = match document.sections.last_mut() {
+                        Some((_, table)) => table,
+                        None => &mut document.root,
+                    };
+                    let key = self.key()?;
+                    if table.get(&key).is_some() {
+                        return Err(idf::ESP_ERR_INVALID_STATE.into());
+                    }
+                    self.skip_blank(false);
+                    self.expect(b'=')?;
+                    self.skip_blank(false);
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    table.entries.push((key, value));
+                }
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.index).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), EspError> {
+        if self.eat(byte) { Ok(()) } else { Err(idf::ESP_ERR_INVALID_ARG.into()) }
+    }
+
+    // skips spaces and comments, and newlines too if `newlines` is set
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(byte) = self.peek() {
+            match byte {
+                b' ' | b'\t' | b'\r' => self.index += 1,
+                b'\n' if newlines => {
+                    self.index += 1;
+                    self.line += 1;
+                }
+                b'#' => {
+                    while let Some(byte) = self.peek() {
+                        if byte == b'\n' {
+                            break;
+                        }
+                        self.index += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), EspError> {
+        self.skip_blank(false);
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.index += 1;
+                self.line += 1;
+                Ok(())
+            }
+            _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, EspError> {
+        if self.peek() == Some(b'"') {
+            return self.string();
+        }
+        let start = self.index;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+                self.index += 1;
+            } else {
+                break;
+            }
+        }
+        if self.index == start {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        Ok(String::from(&self.text[start..self.index]))
+    }
+
+    fn value(&mut self) -> Result<Value, EspError> {
+        match self.peek() {
+            Some(b'"') => Ok(Value::Text(self.string()?)),
+            Some(b'[') => {
+                self.index += 1;
+                let mut list = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.eat(b']') {
+                        return Ok(Value::List(list));
+                    }
+                    list.push(self.value()?);
+                    self.skip_blank(true);
+                    if !self.eat(b',') {
+                        self.skip_blank(true);
+                        self.expect(b']')?;
+                        return Ok(Value::List(list));
+                    }
+                }
+            }
+            Some(b't') | Some(b'f') => {
+                let word = self.key()?;
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+                }
+            }
+            Some(_) => {
+                let start = self.index;
+                while let Some(byte) = self.peek() {
+                    if byte.is_ascii_digit() || byte == b'.' || byte == b'-' || byte == b'+' || byte == b'e' || byte == b'E' {
+                        self.index += 1;
+                    } else {
+                        break;
+                    }
+                }
+                self.text[start..self.index].parse::<f32>()
+                    .map(Value::Number)
+                    .map_err(|_| idf::ESP_ERR_INVALID_ARG.into())
+            }
+            None => Err(idf::ESP_ERR_INVALID_ARG.into()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, EspError> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        let mut start = self.index;
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+                Some(b'"') => {
+                    string.push_str(&self.text[start..self.index]);
+                    self.index += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    string.push_str(&self.text[start..self.index]);
+                    self.index += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+                    };
+                    string.push(escaped);
+                    self.index += 1;
+                    start = self.index;
+                }
+                Some(_) => self.index += 1,
+            }
+        }
+    }
+}
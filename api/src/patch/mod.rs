@@ -0,0 +1,392 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::audio::{Buffer, Config};
+use crate::lfo;
+use crate::logger;
+use crate::matrix::{Curve, Matrix, Source};
+use crate::processor::{Chain, DryWet, Processor, Split};
+
+
+// - modules ------------------------------------------------------------------
+
+pub mod document;
+pub mod node;
+
+
+// - exports ------------------------------------------------------------------
+
+pub use document::{Document, Table, Value};
+pub use node::{Node, Shared};
+
+
+// - global constants ---------------------------------------------------------
+
+const TAG: &str = "api::patch";
+
+// node keys that describe the graph rather than set parameters
+const RESERVED: [&str; 5] = ["id", "type", "nodes", "gains", "dry_wet"];
+
+
+// - patch::Patch -------------------------------------------------------------
+
+// A processor graph loaded at run time from a text patch, e.g.
+//
+//     name = "crunch"
+//     output = "main"
+//
+//     [[node]]
+//     id = "main"
+//     type = "chain"
+//     nodes = ["drive", "tone", "space"]
+//
+//     [[node]]
+//     id = "drive"
+//     type = "saturator"
+//     shape = "tube"
+//     drive = 18
+//
+//     [[node]]
+//     id = "tone"
+//     type = "biquad"
+//     kind = "lowpass"
+//     frequency = 2500
+//
+//     [[node]]
+//     id = "space"
+//     type = "reverb"
+//     size = 0.6
+//     dry_wet = 0.3
+//
+//     [[lfo]]
+//     id = "wobble"
+//     shape = "sine"
+//     rate = 0.5          # Hz, or `beats = 2` to follow the tempo
+//
+//     [[modulation]]
+//     source = "wobble"
+//     target = "tone.frequency"
+//     depth = 1500
+//     minimum = 200       # optional range
+//
+//     [[modulation]]
+//     source = "cc.74"    # or "knob.0", "velocity", an lfo or envelope id
+//     target = "tone.frequency"
+//     depth = 4000
+//     curve = "exponential"
+//
+// Each `[[node]]` names its `type` and sets parameters by name. `chain`
+// nodes run their `nodes` in series, `parallel` nodes run them side by
+// side and sum them with optional `gains`. Any node can be blended with
+// its input with `dry_wet`. Every node is used at most once and the graph
+// starts at `output`.
+//
+// `[[lfo]]` and `[[envelope]]` sections add sources to the patch's
+// modulation matrix, a routing moves a parameter by `depth` times its
+// source around the value set in the node, or `base` if given. The node
+// parameters are updated once per block.
+
+pub struct Patch {
+    pub name: String,
+    pub matrix: Matrix,
+
+    graph: Box<dyn Processor>,
+    nodes: Vec<(String, Shared)>,
+    targets: Vec<(Shared, String)>, // node parameter for each matrix parameter
+}
+
+
+impl Patch {
+    // a patch that does not load any files
+    pub fn parse(text: &str, config: &Config) -> Result<Patch, EspError> {
+        Patch::load(text, config, |_| None)
+    }
+
+    // Parses a patch, files named in the patch, e.g. impulse responses, are
+    // looked up with `resource`.
+    pub fn load<'a, R>(text: &str, config: &Config, resource: R) -> Result<Patch, EspError>
+    where R: Fn(&str) -> Option<&'a [u8]> {
+        let document = Document::parse(text)?;
+        for (section, _) in document.sections.iter() {
+            if !["node", "lfo", "envelope", "modulation"].contains(&section.as_str()) {
+                log!(TAG, "unknown section: [{}]", section);
+                return Err(idf::ESP_ERR_NOT_SUPPORTED.into());
+            }
+        }
+
+        // build the leaf nodes
+        let tables: Vec<&Table> = document.sections("node").collect();
+        let mut ids = Vec::with_capacity(tables.len());
+        let mut leaves = Vec::with_capacity(tables.len());
+        for table in tables.iter() {
+            let id = String::from(table.require("id")?.text()?);
+            if ids.contains(&id) {
+                log!(TAG, "duplicate node: {}", id);
+                return Err(idf::ESP_ERR_INVALID_STATE.into());
+            }
+            let kind = table.require("type")?.text()?;
+            let leaf = match kind {
+                "chain" | "parallel" => None,
+                _ => Some(node::build(kind, table, config, &resource).map_err(|e| {
+                    log!(TAG, "node {}: {:?}", id, e);
+                    e
+                })?),
+            };
+            ids.push(id);
+            leaves.push(leaf);
+        }
+        let nodes = ids.iter().zip(leaves.iter())
+            .filter_map(|(id, leaf)| leaf.as_ref().map(|leaf| (id.clone(), leaf.clone())))
+            .collect();
+
+        // assemble the graph from the output
+        let output = document.root.require("output")?.text()?;
+        let mut builder = Builder {
+            ids: &ids,
+            tables: &tables,
+            leaves: leaves,
+            used: vec![false; ids.len()],
+        };
+        let mut graph = builder.assemble(output)?;
+        graph.prepare(config);
+
+        let mut patch = Patch {
+            name: String::from(document.root.text_or("name", "")?),
+            graph: graph,
+            matrix: Matrix::new(config.fs),
+            nodes: nodes,
+            targets: Vec::new(),
+        };
+
+        // modulation sources by id
+        let mut sources: Vec<(String, Source)> = Vec::new();
+        for table in document.sections("lfo") {
+            let id = String::from(table.require("id")?.text()?);
+            let shape = match table.text_or("shape", "sine")? {
+                "sine" => lfo::Shape::Sine,
+                "triangle" => lfo::Shape::Triangle,
+                "saw" => lfo::Shape::Saw,
+                "square" => lfo::Shape::Square,
+                "random" => lfo::Shape::SampleAndHold,
+                "smooth" => lfo::Shape::SmoothRandom,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            };
+            let rate = match table.get("beats") {
+                Some(beats) => lfo::Rate::Beats(beats.number()?),
+                None => lfo::Rate::Hz(table.number_or("rate", 1.)?),
+            };
+            let index = patch.matrix.add_lfo(shape, rate);
+            patch.matrix.lfos[index].set_phase_offset(table.number_or("phase", 0.)?);
+            sources.push((id, Source::Lfo(index)));
+        }
+        for table in document.sections("envelope") {
+            let id = String::from(table.require("id")?.text()?);
+            let index = patch.matrix.add_envelope(
+                table.number_or("attack", 0.01)?,
+                table.number_or("decay", 0.1)?,
+                table.number_or("sustain", 1.)?,
+                table.number_or("release", 0.1)?,
+            );
+            sources.push((id, Source::Envelope(index)));
+        }
+
+        for table in document.sections("modulation") {
+            let source = table.require("source")?.text()?;
+            let source = match sources.iter().find(|(id, _)| id == source) {
+                Some((_, source)) => *source,
+                None => match parse_source(source) {
+                    Some(source) => source,
+                    None => {
+                        log!(TAG, "unknown modulation source: {}", source);
+                        return Err(idf::ESP_ERR_NOT_FOUND.into());
+                    }
+                },
+            };
+            let curve = match table.text_or("curve", "linear")? {
+                "linear" => Curve::Linear,
+                "exponential" => Curve::Exponential,
+                "logarithmic" => Curve::Logarithmic,
+                "scurve" => Curve::SCurve,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            };
+            let target = table.require("target")?.text()?;
+            let (id, parameter) = split_target(target)?;
+            let index = match ids.iter().position(|node| node == id) {
+                Some(index) => index,
+                None => {
+                    log!(TAG, "unknown modulation target: {}", target);
+                    return Err(idf::ESP_ERR_NOT_FOUND.into());
+                }
+            };
+            let node = patch.node(id).ok_or_else(|| EspError::from(idf::ESP_ERR_NOT_SUPPORTED))?.clone();
+            let base = match table.get("base") {
+                Some(base) => base.number()?,
+                None => tables[index].require(parameter)?.number()?,
+            };
+            // rejects unknown and non-numeric parameters before any routing
+            if let Err(error) = node.borrow_mut().set(parameter, &Value::Number(base)) {
+                log!(TAG, "invalid modulation target: {}", target);
+                return Err(error);
+            }
+            let minimum = table.number_or("minimum", core::f32::MIN)?;
+            let maximum = table.number_or("maximum", core::f32::MAX)?;
+            let index = patch.matrix.add_parameter(target, base, minimum, maximum);
+            if index == patch.targets.len() {
+                patch.targets.push((node, String::from(parameter)));
+            } else if table.get("minimum").is_some() || table.get("maximum").is_some() {
+                patch.matrix.parameters[index].set_range(minimum, maximum);
+            }
+            patch.matrix.connect(source, target, table.number_or("depth", 0.)?, curve)?;
+        }
+        patch.matrix.reset();
+
+        Ok(patch)
+    }
+
+    // leaf node by id, e.g. to read its state
+    pub fn node(&self, id: &str) -> Option<&Shared> {
+        self.nodes.iter().find(|(node, _)| node == id).map(|(_, node)| node)
+    }
+
+    // Sets a parameter given as "node.parameter", modulation routings to it
+    // move around the new value.
+    pub fn set(&mut self, target: &str, value: f32) -> Result<(), EspError> {
+        if let Some(index) = self.matrix.find(target) {
+            self.matrix.parameters[index].set_base(value);
+            return Ok(());
+        }
+        let (id, parameter) = split_target(target)?;
+        let node = self.node(id).ok_or_else(|| EspError::from(idf::ESP_ERR_NOT_FOUND))?;
+        node.borrow_mut().set(parameter, &Value::Number(value))
+    }
+
+    // tempo for lfos synced to beats
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.matrix.set_tempo(tempo);
+    }
+
+    fn modulate(&mut self, frames: usize) {
+        if self.targets.is_empty() {
+            return;
+        }
+        self.matrix.update(frames);
+        for (index, (node, parameter)) in self.targets.iter().enumerate() {
+            // targets are checked to take a number on load
+            let _ = node.borrow_mut().set(parameter, &Value::Number(self.matrix.value(index)));
+        }
+    }
+}
+
+
+impl Processor for Patch {
+    fn prepare(&mut self, config: &Config) {
+        self.graph.prepare(config);
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        self.modulate(buffer.len() / num_channels);
+        self.graph.process(num_channels, buffer);
+    }
+
+    fn reset(&mut self) {
+        self.matrix.reset();
+        self.graph.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.graph.latency()
+    }
+}
+
+
+// - helpers ------------------------------------------------------------------
+
+// "velocity", "knob.<n>" or "cc.<n>"
+fn parse_source(source: &str) -> Option<Source> {
+    if source == "velocity" {
+        return Some(Source::Velocity);
+    }
+    let (kind, index) = split_target(source).ok()?;
+    match kind {
+        "knob" => index.parse().ok().map(Source::Knob),
+        "cc" => index.parse().ok().filter(|cc| *cc < 128).map(Source::Cc),
+        _ => None,
+    }
+}
+
+fn split_target(target: &str) -> Result<(&str, &str), EspError> {
+    let mut parts = target.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(parameter)) if !id.is_empty() && !parameter.is_empty() => Ok((id, parameter)),
+        _ => Err(idf::ESP_ERR_INVALID_ARG.into()),
+    }
+}
+
+struct Builder<'a> {
+    ids: &'a [String],
+    tables: &'a [&'a Table],
+    leaves: Vec<Option<Shared>>,
+    used: Vec<bool>,
+}
+
+impl<'a> Builder<'a> {
+    fn assemble(&mut self, id: &str) -> Result<Box<dyn Processor>, EspError> {
+        let index = match self.ids.iter().position(|node| node == id) {
+            Some(index) => index,
+            None => {
+                log!(TAG, "unknown node: {}", id);
+                return Err(idf::ESP_ERR_NOT_FOUND.into());
+            }
+        };
+        if self.used[index] {
+            log!(TAG, "node used more than once: {}", id);
+            return Err(idf::ESP_ERR_INVALID_STATE.into());
+        }
+        self.used[index] = true;
+
+        let table = self.tables[index];
+        let processor: Box<dyn Processor> = match table.require("type")?.text()? {
+            "chain" => {
+                let mut chain = Chain::new();
+                for child in table.require("nodes")?.list()? {
+                    chain.push(self.assemble(child.text()?)?);
+                }
+                Box::new(chain)
+            }
+            "parallel" => {
+                let children = table.require("nodes")?.list()?;
+                let gains = match table.get("gains") {
+                    Some(gains) => gains.list()?,
+                    None => &[],
+                };
+                let mut split = Split::new();
+                for (n, child) in children.iter().enumerate() {
+                    let gain = match gains.get(n) {
+                        Some(gain) => gain.number()?,
+                        None => 1.,
+                    };
+                    split.push(self.assemble(child.text()?)?, gain);
+                }
+                Box::new(split)
+            }
+            _ => match self.leaves[index].take() {
+                Some(leaf) => Box::new(node::Handle(leaf)),
+                None => return Err(idf::ESP_ERR_INVALID_STATE.into()),
+            },
+        };
+
+        match table.get("dry_wet") {
+            Some(mix) => Ok(Box::new(DryWet::new(processor, mix.number()?))),
+            None => Ok(processor),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+extern crate alloc;
+use alloc::rc::Rc;
+
+use core::cell::RefCell;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::audio::{Buffer, Config};
+use crate::convolution::Convolution;
+use crate::delay::{self, Echo};
+use crate::dynamics::{Compressor, Gate, Limiter};
+use crate::filter::{self, Biquad, Ladder, Svf};
+use crate::modulation::{Chorus, Flanger, Phaser};
+use crate::patch::document::{Table, Value};
+use crate::processor::{Gain, Processor};
+use crate::reverb::Reverb;
+use crate::saturation::{self, Saturator};
+use crate::wav::Wav;
+
+
+// - patch::Node --------------------------------------------------------------
+
+// A processor whose parameters can be set by name, from a patch file or a
+// modulation routing. Unknown names give `ESP_ERR_NOT_FOUND`, values of the
+// wrong type or out of range `ESP_ERR_INVALID_ARG`.
+
+pub trait Node: Processor {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError>;
+}
+
+pub type Shared = Rc<RefCell<dyn Node>>;
+
+
+// processor over a node shared with the patch's parameter routing
+pub struct Handle(pub Shared);
+
+impl Processor for Handle {
+    fn prepare(&mut self, config: &Config) { self.0.borrow_mut().prepare(config) }
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) { self.0.borrow_mut().process(num_channels, buffer) }
+    fn reset(&mut self) { self.0.borrow_mut().reset() }
+    fn latency(&self) -> usize { self.0.borrow().latency() }
+}
+
+
+// - node types ---------------------------------------------------------------
+
+// Builds the node for a `[[node]]` section of `type`, resolving files
+// named by the node, e.g. impulse responses, with `resource`. Parameters
+// only used at construction are read here, the rest are set through
+// `Node::set`.
+pub fn build<'a, R>(kind: &str, table: &Table, config: &Config, resource: &R) -> Result<Shared, EspError>
+where R: Fn(&str) -> Option<&'a [u8]> {
+    let fs = config.fs;
+    let num_channels = config.num_channels;
+    let (node, fixed): (Shared, &[&str]) = match kind {
+        "gain" => (share(Gain::new(0.)), &[]),
+        "biquad" => (share(Biquad::new(filter::Kind::LowPass, fs, 1000., 0.707, num_channels)), &[]),
+        "svf" => (share(Svf::new(filter::svf::Mode::LowPass, fs, 1000., 0.707, num_channels)), &[]),
+        "ladder" => (share(Ladder::new(fs, 1000., 0., num_channels)), &[]),
+        "compressor" => (share(Compressor::new(fs)), &[]),
+        "gate" => (share(Gate::new(fs)), &[]),
+        "limiter" => {
+            let lookahead = table.number_or("lookahead", 0.005)?;
+            (share(Limiter::new(fs, lookahead, num_channels)), &["lookahead"])
+        }
+        "saturator" => {
+            let factor = match table.text_or("oversampling", "x2")? {
+                "x1" => saturation::Factor::X1,
+                "x2" => saturation::Factor::X2,
+                "x4" => saturation::Factor::X4,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            };
+            (share(Saturator::new(fs, saturation::Shape::Tanh, factor, num_channels)), &["oversampling"])
+        }
+        "chorus" => (share(Chorus::new(fs)), &[]),
+        "flanger" => (share(Flanger::new(fs)), &[]),
+        "phaser" => (share(Phaser::new(fs)), &[]),
+        "echo" => {
+            let max_time = table.number_or("max_time", 2.)?;
+            (share(Echo::new(fs, max_time)), &["max_time"])
+        }
+        "reverb" => (share(Reverb::new(fs)), &[]),
+        "convolution" => {
+            let name = table.require("ir")?.text()?;
+            let bytes = resource(name).ok_or_else(|| EspError::from(idf::ESP_ERR_NOT_FOUND))?;
+            let wav = Wav::parse(bytes)?;
+            // one block of frames, `block_length` counts samples
+            let frames = config.block_length / num_channels.max(1);
+            let partition = table.number_or("partition", frames.max(32) as f32)? as usize;
+            let max_length = table.number_or("max_length", 1.)?;
+            let convolution = Convolution::from_wav(fs, num_channels, partition, &wav, max_length)?;
+            (share(convolution), &["ir", "partition", "max_length"])
+        }
+        _ => return Err(idf::ESP_ERR_NOT_SUPPORTED.into()),
+    };
+
+    {
+        let mut node = node.borrow_mut();
+        for (name, value) in table.entries.iter() {
+            if fixed.contains(&name.as_str()) || super::RESERVED.contains(&name.as_str()) {
+                continue;
+            }
+            node.set(name, value)?;
+        }
+        node.prepare(config);
+    }
+    Ok(node)
+}
+
+fn share<N: Node + 'static>(node: N) -> Shared {
+    Rc::new(RefCell::new(node))
+}
+
+
+// - node parameters ----------------------------------------------------------
+
+impl Node for Gain {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "gain" => self.set_gain(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Biquad {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "kind" => self.set_kind(match value.text()? {
+                "lowpass" => filter::Kind::LowPass,
+                "highpass" => filter::Kind::HighPass,
+                "bandpass" => filter::Kind::BandPass,
+                "notch" => filter::Kind::Notch,
+                "peak" => filter::Kind::Peak,
+                "lowshelf" => filter::Kind::LowShelf,
+                "highshelf" => filter::Kind::HighShelf,
+                "allpass" => filter::Kind::AllPass,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            }),
+            "frequency" => self.set_frequency(value.number()?),
+            "q" => self.set_q(value.number()?),
+            "gain" => self.set_gain(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Svf {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "mode" => self.mode = match value.text()? {
+                "lowpass" => filter::svf::Mode::LowPass,
+                "bandpass" => filter::svf::Mode::BandPass,
+                "highpass" => filter::svf::Mode::HighPass,
+                "notch" => filter::svf::Mode::Notch,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            },
+            "cutoff" => self.set_cutoff(value.number()?),
+            "q" => self.set_q(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Ladder {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "cutoff" => self.set_cutoff(value.number()?),
+            "resonance" => self.set_resonance(value.number()?),
+            "drive" => self.set_drive(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Compressor {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "threshold" => self.set_threshold(value.number()?),
+            "ratio" => self.set_ratio(value.number()?),
+            "knee" => self.set_knee(value.number()?),
+            "attack" => self.set_attack(value.number()?),
+            "release" => self.set_release(value.number()?),
+            "makeup" => self.set_makeup(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Gate {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "threshold" => self.set_threshold(value.number()?),
+            "hysteresis" => self.set_hysteresis(value.number()?),
+            "range" => self.set_range(value.number()?),
+            "attack" => self.set_attack(value.number()?),
+            "hold" => self.set_hold(value.number()?),
+            "release" => self.set_release(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Limiter {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "ceiling" => self.set_ceiling(value.number()?),
+            "release" => self.set_release(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Saturator {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "shape" => self.set_shape(match value.text()? {
+                "tanh" => saturation::Shape::Tanh,
+                "softclip" => saturation::Shape::SoftClip,
+                "hardclip" => saturation::Shape::HardClip,
+                "foldback" => saturation::Shape::Foldback,
+                "tube" => saturation::Shape::Tube,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            }),
+            "drive" => self.set_drive(value.number()?),
+            "output" => self.set_output(value.number()?),
+            "mix" => self.set_mix(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+macro_rules! modulated_delay_node {
+    ($type:ty) => {
+        impl Node for $type {
+            fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+                match name {
+                    "rate" => self.set_rate(value.number()?),
+                    "delay" => self.set_delay(value.number()?),
+                    "depth" => self.set_depth(value.number()?),
+                    "feedback" => self.set_feedback(value.number()?),
+                    "mix" => self.set_mix(value.number()?),
+                    "spread" => self.set_spread(value.number()?),
+                    _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+modulated_delay_node!(Chorus);
+modulated_delay_node!(Flanger);
+
+impl Node for Phaser {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "rate" => self.set_rate(value.number()?),
+            "stages" => self.set_stages(value.number()? as usize),
+            "minimum" => {
+                let maximum = self.maximum;
+                self.set_range(value.number()?, maximum);
+            }
+            "maximum" => {
+                let minimum = self.minimum;
+                self.set_range(minimum, value.number()?);
+            }
+            "feedback" => self.set_feedback(value.number()?),
+            "mix" => self.set_mix(value.number()?),
+            "spread" => self.set_spread(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Echo {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "time" => self.set_time(value.number()?),
+            "feedback" => self.set_feedback(value.number()?),
+            "damping" => self.set_damping(value.number()?),
+            "mix" => self.set_mix(value.number()?),
+            "mode" => self.set_mode(match value.text()? {
+                "stereo" => delay::Mode::Stereo,
+                "pingpong" => delay::Mode::PingPong,
+                _ => return Err(idf::ESP_ERR_INVALID_ARG.into()),
+            }),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Reverb {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "size" => self.set_size(value.number()?),
+            "damping" => self.set_damping(value.number()?),
+            "pre_delay" => self.set_pre_delay(value.number()?),
+            "mix" => self.set_mix(value.number()?),
+            "width" => self.set_width(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
+
+impl Node for Convolution {
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), EspError> {
+        match name {
+            "gain" => self.set_gain(value.number()?),
+            "mix" => self.set_mix(value.number()?),
+            _ => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        }
+        Ok(())
+    }
+}
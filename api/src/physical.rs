@@ -0,0 +1,551 @@
+use crate::audio::Buffer;
+use crate::delay::DelayLine;
+use crate::filter::{Biquad, Kind};
+use crate::math;
+use crate::voice::{Note, Voice};
+use crate::wavetable;
+
+
+// - global constants ---------------------------------------------------------
+
+const SILENCE: f32 = 1e-4;
+const MINIMUM_FREQUENCY: f32 = 20.; // lowest string pitch, sets the delay line length
+const STRING_RELEASE: f32 = 0.1;    // seconds, decay once the note is released
+
+// TR-808 cymbal oscillator frequencies in Hz
+const HAT_FREQUENCIES: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540., 800.];
+
+
+// - physical::Decay ----------------------------------------------------------
+
+// exponential decay falling by 60 dB over `time` seconds
+#[derive(Copy, Clone, Debug)]
+struct Decay {
+    value: f32,
+    coefficient: f32,
+}
+
+impl Decay {
+    fn new(fs: f32, time: f32) -> Decay {
+        let mut decay = Decay { value: 0., coefficient: 0. };
+        decay.set_time(fs, time);
+        decay
+    }
+
+    fn set_time(&mut self, fs: f32, time: f32) {
+        self.coefficient = if time <= 0. { 0. } else { math::expf(-6.908 / (time * fs)) };
+    }
+
+    fn trigger(&mut self) {
+        self.value = 1.;
+    }
+
+    #[inline(always)]
+    fn next(&mut self) -> f32 {
+        let value = self.value;
+        self.value *= self.coefficient;
+        value
+    }
+}
+
+#[inline(always)]
+fn add_frame(frame: &mut [f32], y: f32, left: f32, right: f32) {
+    if frame.len() > 1 {
+        frame[0] += y * right;
+        frame[1] += y * left;
+    } else {
+        frame[0] += y;
+    }
+}
+
+
+// - physical::Pluck ----------------------------------------------------------
+
+// Karplus-Strong plucked string.
+//
+// A filtered noise burst one period long excites a delay line tuned to
+// the string's period, a two-point average in the loop damps the high
+// harmonics faster than the fundamental.
+
+pub struct Pluck {
+    pub fs: f32,
+    pub frequency: f32,
+    pub decay: f32,      // seconds, time to fall by 60 dB
+    pub damping: f32,    // 0 - 1, high frequency loss in the loop
+    pub brightness: f32, // 0 - 1, of the excitation
+
+    line: DelayLine,
+    random: math::Random,
+    delay: f32,
+    feedback: f32,
+    release_feedback: f32,
+    released: bool,
+    excitation: usize,
+    excitation_state: f32,
+    previous: f32,
+    level: f32,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl Pluck {
+    pub fn new(fs: f32) -> Pluck {
+        let mut pluck = Pluck {
+            fs: fs,
+            frequency: 220.,
+            decay: 2.,
+            damping: 0.5,
+            brightness: 0.7,
+            line: DelayLine::new((fs / MINIMUM_FREQUENCY) as usize + 4),
+            random: math::Random::new(0x5eed),
+            delay: 0.,
+            feedback: 0.,
+            release_feedback: 0.,
+            released: false,
+            excitation: 0,
+            excitation_state: 0.,
+            previous: 0.,
+            level: 0.,
+            gain: 1.,
+            left: 1.,
+            right: 1.,
+        };
+        pluck.update();
+        pluck
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = math::clamp(frequency, MINIMUM_FREQUENCY, self.fs / 4.);
+        self.update();
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.max(0.01);
+        self.update();
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = math::clamp(damping, 0., 1.);
+        self.update();
+    }
+
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = math::clamp(brightness, 0., 1.);
+    }
+
+    fn update(&mut self) {
+        // the loop filter adds half a sample of delay at full damping
+        let period = self.fs / self.frequency;
+        self.delay = math::clamp(period - 0.5 * self.damping, 1., (self.line.len() - 3) as f32);
+        self.feedback = math::powf(10., -3. / (self.frequency * self.decay));
+        self.release_feedback = math::powf(10., -3. / (self.frequency * STRING_RELEASE));
+    }
+
+    pub fn trigger(&mut self, velocity: f32) {
+        self.gain = velocity;
+        self.released = false;
+        self.excitation = (self.fs / self.frequency) as usize;
+        self.excitation_state = 0.;
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.excitation > 0 || self.level > SILENCE
+    }
+
+    pub fn reset(&mut self) {
+        self.line.clear();
+        self.excitation = 0;
+        self.previous = 0.;
+        self.level = 0.;
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        let mut x = 0.;
+        if self.excitation > 0 {
+            let noise = self.random.next_bipolar();
+            let coefficient = 0.1 + 0.9 * self.brightness;
+            self.excitation_state += (noise - self.excitation_state) * coefficient;
+            x = self.excitation_state * self.gain;
+            self.excitation -= 1;
+        }
+
+        let y = self.line.read_linear(self.delay);
+        let filtered = y + (self.previous - y) * 0.5 * self.damping;
+        self.previous = y;
+        let feedback = if self.released { self.release_feedback } else { self.feedback };
+        let output = x + filtered * feedback;
+        self.line.write(output);
+
+        let magnitude = math::fabsf(output);
+        self.level = if magnitude > self.level { magnitude } else { self.level * 0.9995 };
+        output
+    }
+}
+
+
+impl Voice for Pluck {
+    fn note_on(&mut self, note: &Note, legato: bool) {
+        self.set_frequency(math::note_to_frequency(note.pitch()));
+        let (left, right) = math::pan_gains(note.pan);
+        self.left = left;
+        self.right = right;
+        if !legato {
+            self.trigger(note.velocity * note.gain);
+        }
+    }
+
+    fn note_off(&mut self) {
+        self.release();
+    }
+
+    fn is_active(&self) -> bool {
+        Pluck::is_active(self)
+    }
+
+    fn level(&self) -> f32 {
+        self.level
+    }
+
+    fn reset(&mut self) {
+        Pluck::reset(self);
+    }
+
+    fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for frame in buffer.chunks_mut(num_channels) {
+            let y = self.next();
+            add_frame(frame, y, self.left, self.right);
+        }
+    }
+}
+
+
+// - physical::Kick -----------------------------------------------------------
+
+// Analog style kick: a sine swept down from `frequency * (1 + sweep)` to
+// `frequency`, with an optional noise click and tanh drive.
+
+pub struct Kick {
+    pub fs: f32,
+    pub frequency: f32,   // Hz
+    pub sweep: f32,       // pitch at the start as a multiple of `frequency` above it
+    pub pitch_decay: f32, // seconds
+    pub decay: f32,       // seconds
+    pub click: f32,       // 0 - 1
+    pub drive: f32,       // 0 for none
+
+    phase: f32,
+    pitch: Decay,
+    amplitude: Decay,
+    click_amplitude: Decay,
+    random: math::Random,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl Kick {
+    pub fn new(fs: f32) -> Kick {
+        Kick {
+            fs: fs,
+            frequency: 50.,
+            sweep: 3.,
+            pitch_decay: 0.1,
+            decay: 0.4,
+            click: 0.3,
+            drive: 0.,
+            phase: 0.,
+            pitch: Decay::new(fs, 0.1),
+            amplitude: Decay::new(fs, 0.4),
+            click_amplitude: Decay::new(fs, 0.01),
+            random: math::Random::new(0x6b1c),
+            gain: 1.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_sweep(&mut self, sweep: f32) {
+        self.sweep = sweep.max(0.);
+    }
+
+    pub fn set_pitch_decay(&mut self, pitch_decay: f32) {
+        self.pitch_decay = pitch_decay;
+        self.pitch.set_time(self.fs, pitch_decay);
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+        self.amplitude.set_time(self.fs, decay);
+    }
+
+    pub fn set_click(&mut self, click: f32) {
+        self.click = math::clamp(click, 0., 1.);
+    }
+
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(0.);
+    }
+
+    pub fn trigger(&mut self, velocity: f32) {
+        self.gain = velocity;
+        self.phase = 0.;
+        self.pitch.trigger();
+        self.amplitude.trigger();
+        self.click_amplitude.trigger();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.amplitude.value > SILENCE
+    }
+
+    pub fn reset(&mut self) {
+        self.amplitude.value = 0.;
+        self.click_amplitude.value = 0.;
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        let frequency = self.frequency * (1. + self.sweep * self.pitch.next());
+        let body = wavetable::lookup(&wavetable::SIN, self.phase) * self.amplitude.next();
+        self.phase += frequency / self.fs;
+        if self.phase >= 1. {
+            self.phase -= 1.;
+        }
+        let click = self.random.next_bipolar() * self.click_amplitude.next() * self.click;
+        let y = body + click;
+        let y = if self.drive > 0. { math::tanhf(y * (1. + self.drive)) } else { y };
+        y * self.gain
+    }
+}
+
+
+// - physical::Snare ----------------------------------------------------------
+
+// Two damped body modes plus a highpassed noise burst for the wires.
+
+pub struct Snare {
+    pub fs: f32,
+    pub frequency: f32, // Hz, of the lower body mode
+    pub snappy: f32,    // 0 - 1, body / noise balance
+
+    phases: [f32; 2],
+    body: Decay,
+    noise: Decay,
+    random: math::Random,
+    highpass: Biquad,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl Snare {
+    pub fn new(fs: f32) -> Snare {
+        Snare {
+            fs: fs,
+            frequency: 180.,
+            snappy: 0.6,
+            phases: [0.; 2],
+            body: Decay::new(fs, 0.2),
+            noise: Decay::new(fs, 0.3),
+            random: math::Random::new(0x54a7),
+            highpass: Biquad::new(Kind::HighPass, fs, 1800., 0.7, 1),
+            gain: 1.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_snappy(&mut self, snappy: f32) {
+        self.snappy = math::clamp(snappy, 0., 1.);
+    }
+
+    // seconds
+    pub fn set_decay(&mut self, body: f32, noise: f32) {
+        self.body.set_time(self.fs, body);
+        self.noise.set_time(self.fs, noise);
+    }
+
+    pub fn trigger(&mut self, velocity: f32) {
+        self.gain = velocity;
+        self.phases = [0.; 2];
+        self.body.trigger();
+        self.noise.trigger();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.body.value > SILENCE || self.noise.value > SILENCE
+    }
+
+    pub fn reset(&mut self) {
+        self.body.value = 0.;
+        self.noise.value = 0.;
+        self.highpass.reset();
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        let frequencies = [self.frequency, self.frequency * 1.6];
+        let mut body = 0.;
+        for (phase, frequency) in self.phases.iter_mut().zip(frequencies.iter()) {
+            body += wavetable::lookup(&wavetable::SIN, *phase);
+            *phase += frequency / self.fs;
+            if *phase >= 1. {
+                *phase -= 1.;
+            }
+        }
+        let body = body * 0.5 * self.body.next();
+        let noise = self.highpass.tick(0, self.random.next_bipolar()) * self.noise.next();
+        (body * (1. - self.snappy) + noise * self.snappy) * self.gain
+    }
+}
+
+
+// - physical::Hat ------------------------------------------------------------
+
+// TR-808 style hi-hat: six detuned square waves through a bandpass and
+// highpass filter. Use a short decay for closed and a long one for open,
+// `choke` cuts an open hat off.
+
+pub struct Hat {
+    pub fs: f32,
+    pub tune: f32, // multiplier on the oscillator frequencies
+
+    phases: [f32; 6],
+    amplitude: Decay,
+    bandpass: Biquad,
+    highpass: Biquad,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl Hat {
+    pub fn new(fs: f32) -> Hat {
+        Hat {
+            fs: fs,
+            tune: 1.,
+            phases: [0.; 6],
+            amplitude: Decay::new(fs, 0.08),
+            bandpass: Biquad::new(Kind::BandPass, fs, (10_000f32).min(fs * 0.4), 1., 1),
+            highpass: Biquad::new(Kind::HighPass, fs, (7_000f32).min(fs * 0.3), 0.7, 1),
+            gain: 1.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    pub fn set_tune(&mut self, tune: f32) {
+        self.tune = tune.max(0.);
+    }
+
+    // seconds
+    pub fn set_decay(&mut self, decay: f32) {
+        self.amplitude.set_time(self.fs, decay);
+    }
+
+    pub fn trigger(&mut self, velocity: f32) {
+        self.gain = velocity;
+        self.amplitude.trigger();
+    }
+
+    pub fn choke(&mut self) {
+        self.amplitude.value = 0.;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.amplitude.value > SILENCE
+    }
+
+    pub fn reset(&mut self) {
+        self.amplitude.value = 0.;
+        self.bandpass.reset();
+        self.highpass.reset();
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        let mut x = 0.;
+        for (phase, frequency) in self.phases.iter_mut().zip(HAT_FREQUENCIES.iter()) {
+            x += if *phase < 0.5 { 1. } else { -1. };
+            *phase += frequency * self.tune / self.fs;
+            if *phase >= 1. {
+                *phase -= 1.;
+            }
+        }
+        let x = self.bandpass.tick(0, x * (1. / 6.));
+        let y = self.highpass.tick(0, x);
+        y * self.amplitude.next() * self.gain
+    }
+}
+
+
+// - drums as voices ----------------------------------------------------------
+
+// Drums are one-shots tuned by their own parameters: note on triggers
+// with the note velocity and pan, note off is ignored.
+
+macro_rules! drum_voice {
+    ($name:ident, $level:expr) => {
+        impl Voice for $name {
+            fn note_on(&mut self, note: &Note, legato: bool) {
+                let (left, right) = math::pan_gains(note.pan);
+                self.left = left;
+                self.right = right;
+                if !legato {
+                    self.trigger(note.velocity * note.gain);
+                }
+            }
+
+            fn note_off(&mut self) {
+            }
+
+            fn is_active(&self) -> bool {
+                $name::is_active(self)
+            }
+
+            fn level(&self) -> f32 {
+                let level: fn(&$name) -> f32 = $level;
+                level(self) * self.gain
+            }
+
+            fn reset(&mut self) {
+                $name::reset(self);
+            }
+
+            fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+                if num_channels == 0 {
+                    return;
+                }
+                for frame in buffer.chunks_mut(num_channels) {
+                    let y = self.next();
+                    add_frame(frame, y, self.left, self.right);
+                }
+            }
+        }
+    }
+}
+
+drum_voice!(Kick, |kick| kick.amplitude.value);
+drum_voice!(Snare, |snare| snare.body.value.max(snare.noise.value));
+drum_voice!(Hat, |hat| hat.amplitude.value);
@@ -0,0 +1,260 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::audio::Buffer;
+use crate::fft::{Complex, Fft, TapReader, MAX_SIZE};
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+
+// - pitch::Reading -----------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Reading {
+    pub frequency: f32,
+    pub note: u8,         // nearest midi note
+    pub cents: f32,       // -50 - 50, deviation from the nearest note
+    pub confidence: f32,  // 0 - 1
+}
+
+impl Reading {
+    pub fn name(&self) -> &'static str {
+        NOTE_NAMES[self.note as usize % 12]
+    }
+
+    pub fn octave(&self) -> i32 {
+        self.note as i32 / 12 - 1
+    }
+}
+
+
+// - pitch::Detector ----------------------------------------------------------
+
+// YIN pitch detector for monophonic input, e.g. a guitar or bass tuner.
+//
+// Input is mixed down to mono and a new reading is made every `hop` frames
+// over enough input to hold two periods of `min_frequency`. The
+// difference function is computed through an FFT, for low frequencies
+// that is still too much work for the audio closure: feed the detector
+// through a `fft::tap` and call `update` from the display loop instead.
+//
+// See: de Cheveigné & Kawahara, "YIN, a fundamental frequency estimator
+//      for speech and music", 2002
+
+pub struct Detector {
+    pub fs: f32,
+    pub threshold: f32, // 0 - 1, lower is stricter
+    pub gate: f32,      // dBFS, quieter input gives no reading
+    pub reference: f32, // Hz, frequency of A4
+
+    window: usize,
+    tau_min: usize,
+    tau_max: usize,
+    hop: usize,
+
+    fft: Fft,
+    input: Vec<f32>,
+    count: usize,
+    frame: Vec<f32>,
+    signal: Vec<Complex>,
+    kernel: Vec<Complex>,
+    difference: Vec<f32>,
+    reading: Option<Reading>,
+}
+
+
+impl Detector {
+    pub fn new(fs: f32, min_frequency: f32, max_frequency: f32) -> Result<Detector, EspError> {
+        if min_frequency <= 0. || max_frequency <= min_frequency || max_frequency > fs * 0.25 {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        let tau_min = ((fs / max_frequency) as usize).max(2);
+        let tau_max = (fs / min_frequency) as usize + 2;
+        let window = tau_max;
+        let length = window + tau_max + 1;
+        if length > MAX_SIZE {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        let size = length.next_power_of_two().max(crate::fft::MIN_SIZE);
+        let fft = Fft::new(size)?;
+        let bins = fft.bins();
+
+        Ok(Detector {
+            fs: fs,
+            threshold: 0.15,
+            gate: -60.,
+            reference: 440.,
+            window: window,
+            tau_min: tau_min,
+            tau_max: tau_max,
+            hop: window / 2,
+            fft: fft,
+            input: vec![0.; length],
+            count: 0,
+            frame: vec![0.; size],
+            signal: vec![Complex::default(); bins],
+            kernel: vec![Complex::default(); bins],
+            difference: vec![0.; tau_max + 1],
+            reading: None,
+        })
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = math::clamp(threshold, 0.01, 1.);
+    }
+
+    pub fn set_gate(&mut self, gate: f32) {
+        self.gate = gate;
+    }
+
+    pub fn set_reference(&mut self, reference: f32) {
+        self.reference = math::clamp(reference, 400., 480.);
+    }
+
+    // frames between readings
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    // most recent reading, none while the input is gated or unpitched
+    pub fn reading(&self) -> Option<Reading> {
+        self.reading
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.reading = None;
+    }
+
+    // Feeds an interleaved buffer with `num_channels` channels, returns true
+    // if a new reading was made.
+    pub fn process(&mut self, num_channels: usize, buffer: &Buffer) -> bool {
+        if num_channels == 0 {
+            return false;
+        }
+        let scale = 1. / num_channels as f32;
+        let mut updated = false;
+        for frame in buffer.chunks(num_channels) {
+            let x: f32 = frame.iter().sum();
+            self.input[self.count] = x * scale;
+            self.count += 1;
+            if self.count == self.input.len() {
+                self.advance();
+                updated = true;
+            }
+        }
+        updated
+    }
+
+    // Drains a tap, returns true if a new reading was made.
+    pub fn update(&mut self, reader: &mut TapReader) -> bool {
+        let mut updated = false;
+        loop {
+            self.count += reader.pop(&mut self.input[self.count..]);
+            if self.count < self.input.len() {
+                break;
+            }
+            self.advance();
+            updated = true;
+        }
+        updated
+    }
+
+    fn advance(&mut self) {
+        self.reading = self.detect();
+        self.input.copy_within(self.hop.., 0);
+        self.count = self.input.len() - self.hop;
+    }
+
+    fn detect(&mut self) -> Option<Reading> {
+        let window = self.window;
+        let length = self.input.len();
+
+        // energy of the first window and gate
+        let mut energy: f32 = self.input[..window].iter().map(|x| x * x).sum();
+        let level = math::gain_to_db(math::sqrtf(energy / window as f32));
+        if level < self.gate {
+            return None;
+        }
+
+        // r(tau) = sum x[j] x[j + tau] over the window, via the spectrum
+        self.frame[..length].copy_from_slice(&self.input);
+        for y in self.frame[length..].iter_mut() {
+            *y = 0.;
+        }
+        self.fft.forward(&self.frame, &mut self.signal);
+        for y in self.frame[window..].iter_mut() {
+            *y = 0.;
+        }
+        self.fft.forward(&self.frame, &mut self.kernel);
+        for (x, k) in self.signal.iter_mut().zip(&self.kernel) {
+            *x = *x * k.conj();
+        }
+        self.fft.inverse(&self.signal, &mut self.frame);
+
+        // cumulative mean normalized difference
+        // d(tau) = e(0) + e(tau) - 2 r(tau)
+        let e0 = energy;
+        let mut sum = 0.;
+        self.difference[0] = 1.;
+        for tau in 1..=self.tau_max {
+            let x0 = self.input[tau - 1];
+            let x1 = self.input[tau - 1 + window];
+            energy += x1 * x1 - x0 * x0;
+            let d = (e0 + energy - 2. * self.frame[tau]).max(0.);
+            self.frame[tau] = d;
+            sum += d;
+            self.difference[tau] = if sum > 0. { d * tau as f32 / sum } else { 1. };
+        }
+
+        // first dip below the threshold, else the global minimum
+        let last = self.tau_max - 1;
+        let mut best = self.tau_min;
+        let mut found = false;
+        for tau in self.tau_min..last {
+            if self.difference[tau] < self.threshold {
+                best = tau;
+                while best < last && self.difference[best + 1] < self.difference[best] {
+                    best += 1;
+                }
+                found = true;
+                break;
+            }
+            if self.difference[tau] < self.difference[best] {
+                best = tau;
+            }
+        }
+        if !found && self.difference[best] >= 0.5 {
+            return None;
+        }
+
+        // refine the period with a parabola through the neighbouring values
+        // of the raw difference, left in `frame`
+        let (a, b, c) = (self.frame[best - 1], self.frame[best], self.frame[best + 1]);
+        let denominator = a - 2. * b + c;
+        let offset = if denominator > 0. { math::clamp(0.5 * (a - c) / denominator, -0.5, 0.5) } else { 0. };
+        let frequency = self.fs / (best as f32 + offset);
+
+        let pitch = 69. + 12. * math::log2f(frequency / self.reference);
+        if pitch < 0. || pitch > 127. {
+            return None;
+        }
+        let note = math::floorf(pitch + 0.5);
+        Some(Reading {
+            frequency: frequency,
+            note: note as u8,
+            cents: (pitch - note) * 100.,
+            confidence: math::clamp(1. - self.difference[best], 0., 1.),
+        })
+    }
+}
@@ -0,0 +1,541 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::ops::DerefMut;
+
+use crate::audio::{Buffer, Config};
+use crate::convolution::Convolution;
+use crate::delay::Echo;
+use crate::dynamics::{Compressor, Gate, Limiter};
+use crate::filter::{Biquad, Cascade, Ladder, Svf};
+use crate::granular::Granular;
+use crate::math;
+use crate::modulation::{Chorus, Flanger, ModulatedDelay, Phaser};
+use crate::reverb::Reverb;
+use crate::sample::Source;
+use crate::saturation::Saturator;
+use crate::voice::{Voice, Voices};
+
+
+// - processor::Processor -----------------------------------------------------
+
+// Block based audio processor operating in place on an interleaved buffer
+// with `num_channels` channels, the building block of an audio graph.
+//
+// `prepare` is called with the interface configuration before the first
+// block, allocate anything sized by the block length there rather than in
+// `process`. Note that `config.block_length` counts samples across all
+// channels, not frames. `latency` reports the delay added to the signal in
+// frames so parallel paths can be aligned.
+
+pub trait Processor {
+    fn prepare(&mut self, _config: &Config) {}
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer);
+
+    fn reset(&mut self) {}
+
+    fn latency(&self) -> usize {
+        0
+    }
+
+    // run `next` on the output of this processor
+    fn then<P: Processor>(self, next: P) -> Serial<Self, P> where Self: Sized {
+        Serial::new(self, next)
+    }
+
+    // run `other` on a copy of the input and sum the outputs
+    fn alongside<P: Processor>(self, other: P) -> Parallel<Self, P> where Self: Sized {
+        Parallel::new(self, other)
+    }
+
+    // blend the output with the dry input
+    fn dry_wet(self, mix: f32) -> DryWet<Self> where Self: Sized {
+        DryWet::new(self, mix)
+    }
+}
+
+
+impl<P: Processor + ?Sized> Processor for Box<P> {
+    fn prepare(&mut self, config: &Config) { (**self).prepare(config) }
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) { (**self).process(num_channels, buffer) }
+    fn reset(&mut self) { (**self).reset() }
+    fn latency(&self) -> usize { (**self).latency() }
+}
+
+impl<'a, P: Processor + ?Sized> Processor for &'a mut P {
+    fn prepare(&mut self, config: &Config) { (**self).prepare(config) }
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) { (**self).process(num_channels, buffer) }
+    fn reset(&mut self) { (**self).reset() }
+    fn latency(&self) -> usize { (**self).latency() }
+}
+
+
+// - processor::FromFn --------------------------------------------------------
+
+// Processor from a closure, for one off glue inside a graph.
+
+pub struct FromFn<F> {
+    f: F,
+}
+
+pub fn from_fn<F: FnMut(usize, &mut Buffer)>(f: F) -> FromFn<F> {
+    FromFn { f: f }
+}
+
+impl<F: FnMut(usize, &mut Buffer)> Processor for FromFn<F> {
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        (self.f)(num_channels, buffer)
+    }
+}
+
+
+// - processor::Gain ----------------------------------------------------------
+
+pub struct Gain {
+    pub gain: f32, // dB
+
+    level: f32,
+}
+
+impl Gain {
+    pub fn new(gain: f32) -> Gain {
+        Gain {
+            gain: gain,
+            level: math::db_to_gain(gain),
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+        self.level = math::db_to_gain(gain);
+    }
+}
+
+impl Processor for Gain {
+    fn process(&mut self, _num_channels: usize, buffer: &mut Buffer) {
+        for x in buffer.iter_mut() {
+            *x *= self.level;
+        }
+    }
+}
+
+
+// - processor::Compensation --------------------------------------------------
+
+// Delays a signal by whole frames to line it up with a path that has more
+// latency. Storage is allocated in `prepare`, a delay that no longer fits
+// afterwards is left uncompensated until the next `prepare`.
+
+struct Compensation {
+    buffer: Vec<f32>,
+    length: usize, // samples in use
+    index: usize,
+}
+
+impl Compensation {
+    fn new() -> Compensation {
+        Compensation {
+            buffer: Vec::new(),
+            length: 0,
+            index: 0,
+        }
+    }
+
+    fn prepare(&mut self, delay: usize, num_channels: usize) {
+        self.buffer = vec![0.; delay * num_channels];
+        self.length = self.buffer.len();
+        self.index = 0;
+    }
+
+    fn reset(&mut self) {
+        for x in self.buffer.iter_mut() {
+            *x = 0.;
+        }
+        self.index = 0;
+    }
+
+    fn process(&mut self, delay: usize, num_channels: usize, buffer: &mut Buffer) {
+        let length = delay * num_channels;
+        if length == 0 || length > self.buffer.len() {
+            return;
+        }
+        if length != self.length {
+            self.length = length;
+            self.reset();
+        }
+        for x in buffer.iter_mut() {
+            core::mem::swap(x, &mut self.buffer[self.index]);
+            self.index += 1;
+            if self.index == length {
+                self.index = 0;
+            }
+        }
+    }
+}
+
+
+// scratch buffer for a copy of the block, sized in `prepare`
+fn scratch<'a>(scratch: &'a mut Vec<f32>, buffer: &Buffer) -> &'a mut Buffer {
+    if scratch.len() < buffer.len() {
+        scratch.resize(buffer.len(), 0.);
+    }
+    let scratch = &mut scratch[..buffer.len()];
+    scratch.copy_from_slice(buffer);
+    scratch
+}
+
+
+// - processor::Serial --------------------------------------------------------
+
+pub struct Serial<A, B> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<A: Processor, B: Processor> Serial<A, B> {
+    pub fn new(first: A, second: B) -> Serial<A, B> {
+        Serial {
+            first: first,
+            second: second,
+        }
+    }
+}
+
+impl<A: Processor, B: Processor> Processor for Serial<A, B> {
+    fn prepare(&mut self, config: &Config) {
+        self.first.prepare(config);
+        self.second.prepare(config);
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        self.first.process(num_channels, buffer);
+        self.second.process(num_channels, buffer);
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.first.latency() + self.second.latency()
+    }
+}
+
+
+// - processor::Parallel ------------------------------------------------------
+
+// Runs both processors on the same input and sums their outputs, the path
+// with less latency is delayed to match the other.
+
+pub struct Parallel<A, B> {
+    pub first: A,
+    pub second: B,
+
+    scratch: Vec<f32>,
+    compensation: [Compensation; 2],
+}
+
+impl<A: Processor, B: Processor> Parallel<A, B> {
+    pub fn new(first: A, second: B) -> Parallel<A, B> {
+        Parallel {
+            first: first,
+            second: second,
+            scratch: Vec::new(),
+            compensation: [Compensation::new(), Compensation::new()],
+        }
+    }
+}
+
+impl<A: Processor, B: Processor> Processor for Parallel<A, B> {
+    fn prepare(&mut self, config: &Config) {
+        self.first.prepare(config);
+        self.second.prepare(config);
+        self.scratch = vec![0.; config.block_length];
+        let latency = self.latency();
+        self.compensation[0].prepare(latency - self.first.latency(), config.num_channels);
+        self.compensation[1].prepare(latency - self.second.latency(), config.num_channels);
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        let latency = self.latency();
+        let copy = scratch(&mut self.scratch, buffer);
+        self.first.process(num_channels, buffer);
+        self.second.process(num_channels, copy);
+        self.compensation[0].process(latency - self.first.latency(), num_channels, buffer);
+        self.compensation[1].process(latency - self.second.latency(), num_channels, copy);
+        for (y, x) in buffer.iter_mut().zip(copy.iter()) {
+            *y += x;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+        for compensation in self.compensation.iter_mut() {
+            compensation.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.first.latency().max(self.second.latency())
+    }
+}
+
+
+// - processor::DryWet --------------------------------------------------------
+
+// Blends a processor's output with its input, the dry signal is delayed by
+// the processor's latency.
+
+pub struct DryWet<P> {
+    pub processor: P,
+    pub mix: f32, // 0 - 1, dry / wet
+
+    dry: Vec<f32>,
+    compensation: Compensation,
+}
+
+impl<P: Processor> DryWet<P> {
+    pub fn new(processor: P, mix: f32) -> DryWet<P> {
+        DryWet {
+            processor: processor,
+            mix: math::clamp(mix, 0., 1.),
+            dry: Vec::new(),
+            compensation: Compensation::new(),
+        }
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+}
+
+impl<P: Processor> Processor for DryWet<P> {
+    fn prepare(&mut self, config: &Config) {
+        self.processor.prepare(config);
+        self.dry = vec![0.; config.block_length];
+        self.compensation.prepare(self.processor.latency(), config.num_channels);
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        let latency = self.processor.latency();
+        let dry = scratch(&mut self.dry, buffer);
+        self.processor.process(num_channels, buffer);
+        self.compensation.process(latency, num_channels, dry);
+        let wet = self.mix;
+        for (y, x) in buffer.iter_mut().zip(dry.iter()) {
+            *y = x + (*y - x) * wet;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.processor.reset();
+        self.compensation.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.processor.latency()
+    }
+}
+
+
+// - processor::Chain ---------------------------------------------------------
+
+// Serial chain of processors chosen at run time.
+
+pub struct Chain {
+    pub processors: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain {
+            processors: Vec::new(),
+        }
+    }
+
+    pub fn push<P: Processor + 'static>(&mut self, processor: P) {
+        self.processors.push(Box::new(processor));
+    }
+}
+
+impl Processor for Chain {
+    fn prepare(&mut self, config: &Config) {
+        for processor in self.processors.iter_mut() {
+            processor.prepare(config);
+        }
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        for processor in self.processors.iter_mut() {
+            processor.process(num_channels, buffer);
+        }
+    }
+
+    fn reset(&mut self) {
+        for processor in self.processors.iter_mut() {
+            processor.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.processors.iter().map(|processor| processor.latency()).sum()
+    }
+}
+
+
+// - processor::Split ---------------------------------------------------------
+
+// Parallel branches chosen at run time, each runs on a copy of the input
+// and the outputs are summed with a gain per branch. Branches are delayed
+// to match the one with the most latency.
+
+pub struct Split {
+    pub branches: Vec<Box<dyn Processor>>,
+    pub gains: Vec<f32>,
+
+    scratch: Vec<f32>,
+    output: Vec<f32>,
+    compensation: Vec<Compensation>,
+}
+
+impl Split {
+    pub fn new() -> Split {
+        Split {
+            branches: Vec::new(),
+            gains: Vec::new(),
+            scratch: Vec::new(),
+            output: Vec::new(),
+            compensation: Vec::new(),
+        }
+    }
+
+    pub fn push<P: Processor + 'static>(&mut self, processor: P, gain: f32) {
+        self.branches.push(Box::new(processor));
+        self.gains.push(gain);
+        self.compensation.push(Compensation::new());
+    }
+}
+
+impl Processor for Split {
+    fn prepare(&mut self, config: &Config) {
+        for branch in self.branches.iter_mut() {
+            branch.prepare(config);
+        }
+        self.scratch = vec![0.; config.block_length];
+        self.output = vec![0.; config.block_length];
+        let latency = self.latency();
+        for (branch, compensation) in self.branches.iter().zip(self.compensation.iter_mut()) {
+            compensation.prepare(latency - branch.latency(), config.num_channels);
+        }
+    }
+
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        let latency = self.latency();
+        if self.output.len() < buffer.len() {
+            self.output.resize(buffer.len(), 0.);
+        }
+        let output = &mut self.output[..buffer.len()];
+        for x in output.iter_mut() {
+            *x = 0.;
+        }
+        for ((branch, gain), compensation) in self.branches.iter_mut().zip(&self.gains).zip(self.compensation.iter_mut()) {
+            let copy = scratch(&mut self.scratch, buffer);
+            branch.process(num_channels, copy);
+            compensation.process(latency - branch.latency(), num_channels, copy);
+            for (y, x) in output.iter_mut().zip(copy.iter()) {
+                *y += x * gain;
+            }
+        }
+        buffer.copy_from_slice(output);
+    }
+
+    fn reset(&mut self) {
+        for branch in self.branches.iter_mut() {
+            branch.reset();
+        }
+        for compensation in self.compensation.iter_mut() {
+            compensation.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.branches.iter().map(|branch| branch.latency()).max().unwrap_or(0)
+    }
+}
+
+
+// - processor impls ----------------------------------------------------------
+
+macro_rules! processor {
+    ($type:ty) => {
+        processor!($type, |_processor| 0);
+    };
+    ($type:ty, |$processor:ident| $latency:expr) => {
+        impl Processor for $type {
+            fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+                <$type>::process(self, num_channels, buffer)
+            }
+
+            fn reset(&mut self) {
+                <$type>::reset(self)
+            }
+
+            fn latency(&self) -> usize {
+                let $processor = self;
+                $latency
+            }
+        }
+    };
+}
+
+processor!(Biquad);
+processor!(Cascade);
+processor!(Chorus);
+processor!(Compressor);
+processor!(Convolution);
+processor!(Flanger);
+processor!(Gate);
+processor!(Ladder);
+processor!(Limiter, |limiter| limiter.latency());
+processor!(ModulatedDelay);
+processor!(Phaser);
+processor!(Reverb);
+processor!(Saturator, |saturator| saturator.latency());
+processor!(Svf);
+
+
+impl<B: DerefMut<Target = [f32]>> Processor for Echo<B> {
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        Echo::process(self, num_channels, buffer)
+    }
+
+    fn reset(&mut self) {
+        Echo::reset(self)
+    }
+}
+
+impl<S: Source> Processor for Granular<S> {
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        Granular::process(self, num_channels, buffer)
+    }
+
+    fn reset(&mut self) {
+        Granular::reset(self)
+    }
+}
+
+// voices replace the buffer contents, put them first in a chain
+impl<V: Voice> Processor for Voices<V> {
+    fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        Voices::process(self, num_channels, buffer)
+    }
+
+    fn reset(&mut self) {
+        Voices::reset(self)
+    }
+}
@@ -0,0 +1,354 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+pub const MIN_RATIO: f32 = 0.25;
+pub const MAX_RATIO: f32 = 4.;
+
+
+// - resample::Quality --------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Quality {
+    Draft,  // ~60 dB alias rejection, passband to 10 kHz at 44.1 kHz
+    Normal, // ~90 dB alias rejection, passband to 15 kHz at 44.1 kHz
+    High,   // better than 95 dB alias rejection, passband to 18 kHz at 44.1 kHz
+}
+
+
+impl Quality {
+    // zero crossings each side, table phases per zero crossing, kaiser
+    // beta and cutoff as a fraction of the nyquist frequency
+    fn design(&self) -> (usize, usize, f32, f32) {
+        match self {
+            Quality::Draft => (4, 64, 5., 0.85),
+            Quality::Normal => (8, 128, 7.5, 0.9),
+            Quality::High => (16, 256, 10., 0.93),
+        }
+    }
+}
+
+
+// - resample::Resampler ------------------------------------------------------
+
+// Polyphase windowed-sinc sample rate converter.
+//
+// The kaiser windowed sinc is tabulated at `phases` points per zero
+// crossing and linearly interpolated, so any ratio of output to input
+// rate between `MIN_RATIO` and `MAX_RATIO` can be used and changed while
+// running, e.g. to track clock drift. When downsampling the kernel is
+// stretched to move the cutoff below the output nyquist frequency.
+//
+// Audio is interleaved. Output is delayed by `latency()` input frames.
+
+pub struct Resampler {
+    pub quality: Quality,
+    pub num_channels: usize,
+
+    ratio: f32,
+    step: f32,
+    table: Vec<f32>,
+    phases: usize,
+    half_width: usize,
+    reach: usize, // input frames each side of the centre at MIN_RATIO
+    coefficients: Vec<f32>,
+    history: Vec<Vec<f32>>,
+    index: usize,
+    phase: f32, // position of the next output after the centre frame
+}
+
+
+impl Resampler {
+    // `ratio` is the output rate over the input rate
+    pub fn new(quality: Quality, num_channels: usize, ratio: f32) -> Resampler {
+        let (half_width, phases, beta, cutoff) = quality.design();
+
+        // one side of the prototype kernel, plus guard points for the
+        // interpolation
+        let points = half_width * phases;
+        let i0_beta = math::bessel_i0(beta);
+        let mut table = Vec::with_capacity(points + 2);
+        for j in 0..points {
+            let t = j as f32 / phases as f32;
+            let x = math::PI * cutoff * t;
+            let sinc = if j == 0 { 1. } else { math::sinf(x) / x };
+            let r = t / half_width as f32;
+            let window = math::bessel_i0(beta * math::sqrtf(1. - r * r)) / i0_beta;
+            table.push(cutoff * sinc * window);
+        }
+        table.push(0.);
+        table.push(0.);
+
+        let reach = (half_width as f32 / MIN_RATIO) as usize + 1;
+        let length = 2 * reach + 1;
+        let num_channels = num_channels.max(1);
+
+        let mut resampler = Resampler {
+            quality: quality,
+            num_channels: num_channels,
+            ratio: 1.,
+            step: 1.,
+            table: table,
+            phases: phases,
+            half_width: half_width,
+            reach: reach,
+            coefficients: vec![0.; 2 * reach],
+            history: (0..num_channels).map(|_| vec![0.; 2 * length]).collect(),
+            index: 0,
+            phase: 0.,
+        };
+        resampler.set_ratio(ratio);
+        resampler
+    }
+
+    pub fn from_rates(quality: Quality, num_channels: usize, fs_in: f32, fs_out: f32) -> Resampler {
+        Resampler::new(quality, num_channels, fs_out / fs_in)
+    }
+
+    pub fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    // takes effect from the next output frame
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = math::clamp(ratio, MIN_RATIO, MAX_RATIO);
+        self.step = 1. / self.ratio;
+    }
+
+    // in input frames
+    pub fn latency(&self) -> usize {
+        self.reach
+    }
+
+    pub fn reset(&mut self) {
+        for history in self.history.iter_mut() {
+            for sample in history.iter_mut() {
+                *sample = 0.;
+            }
+        }
+        self.index = 0;
+        self.phase = 0.;
+    }
+
+    // Converts as much of `input` into `output` as fits, both interleaved.
+    // Returns the number of input frames consumed and output frames
+    // produced. Unconsumed input must be passed again on the next call.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize) {
+        let num_channels = self.num_channels;
+        let frames_in = input.len() / num_channels;
+        let frames_out = output.len() / num_channels;
+        let mut consumed = 0;
+        let mut produced = 0;
+
+        while produced < frames_out {
+            while self.phase >= 1. {
+                if consumed == frames_in {
+                    return (consumed, produced);
+                }
+                let start = consumed * num_channels;
+                self.push(&input[start..start + num_channels]);
+                consumed += 1;
+                self.phase -= 1.;
+            }
+
+            let extent = self.prepare();
+            let taps = 2 * extent;
+            // the newest frame is `reach` frames after the centre
+            let newest = self.index + 2 * self.reach;
+            let start = newest - self.reach - (extent - 1);
+            let frame = &mut output[produced * num_channels..(produced + 1) * num_channels];
+            for (channel, y) in frame.iter_mut().enumerate() {
+                let window = &self.history[channel][start..start + taps];
+                let mut sum = 0.;
+                for (x, h) in window.iter().zip(self.coefficients[..taps].iter()) {
+                    sum += x * h;
+                }
+                *y = sum;
+            }
+            produced += 1;
+            self.phase += self.step;
+        }
+        (consumed, produced)
+    }
+
+    fn push(&mut self, frame: &[f32]) {
+        let length = 2 * self.reach + 1;
+        for (history, &x) in self.history.iter_mut().zip(frame.iter()) {
+            history[self.index] = x;
+            history[self.index + length] = x;
+        }
+        self.index += 1;
+        if self.index == length {
+            self.index = 0;
+        }
+    }
+
+    // Fills in the coefficients for the current phase, from the oldest
+    // tap to the newest. Returns the number of frames used each side of
+    // the centre frame.
+    fn prepare(&mut self) -> usize {
+        let scale = if self.ratio < 1. { self.ratio } else { 1. };
+        let extent = (self.half_width as f32 / scale) as usize + 1;
+        let extent = if extent > self.reach { self.reach } else { extent };
+        let phases = self.phases as f32;
+        let limit = self.table.len() - 2;
+
+        // tap n sits at offset n - (extent - 1) from the centre frame
+        for n in 0..2 * extent {
+            let t = n as f32 - (extent - 1) as f32 - self.phase;
+            let t = if t < 0. { -t } else { t };
+            let position = t * scale * phases;
+            let index = position as usize;
+            self.coefficients[n] = if index >= limit {
+                0.
+            } else {
+                let frac_part = position - index as f32;
+                let h0 = self.table[index];
+                let h1 = self.table[index + 1];
+                (h0 + (h1 - h0) * frac_part) * scale
+            };
+        }
+        extent
+    }
+}
+
+
+// - resample::resample -------------------------------------------------------
+
+// Converts a complete interleaved buffer, e.g. a sample at load time.
+// The output is aligned with the input, without the streaming latency.
+pub fn resample(quality: Quality, num_channels: usize, ratio: f32, input: &[f32]) -> Vec<f32> {
+    let mut resampler = Resampler::new(quality, num_channels, ratio);
+    let num_channels = resampler.num_channels;
+    let latency = resampler.latency();
+    let frames_in = input.len() / num_channels;
+    let frames_out = (frames_in as f32 * resampler.ratio()) as usize;
+
+    let mut padded = Vec::with_capacity((frames_in + 2 * latency) * num_channels);
+    padded.extend_from_slice(&input[..frames_in * num_channels]);
+    padded.resize((frames_in + 2 * latency) * num_channels, 0.);
+
+    // load the history so the first output lands on the first input frame
+    let preload = (latency + 1) * num_channels;
+    for frame in padded[..preload].chunks(num_channels) {
+        resampler.push(frame);
+    }
+    let mut output = vec![0.; frames_out * num_channels];
+    resampler.process(&padded[preload..], &mut output);
+    output
+}
+
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    extern crate std;
+
+    use alloc::vec::Vec;
+
+    use crate::math;
+    use super::{Quality, Resampler, resample};
+
+    const QUALITIES: [Quality; 3] = [Quality::Draft, Quality::Normal, Quality::High];
+
+    // magnitude of a single dft bin
+    fn goertzel(x: &[f32], fs: f32, frequency: f32) -> f32 {
+        let w = math::TAU * frequency / fs;
+        let coefficient = 2. * math::cosf(w);
+        let (mut s1, mut s2) = (0., 0.);
+        for &sample in x.iter() {
+            let s0 = sample + coefficient * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+        2. * math::sqrtf(power.max(0.)) / x.len() as f32
+    }
+
+    fn sine(fs: f32, frequency: f32, frames: usize) -> Vec<f32> {
+        (0..frames).map(|n| math::sinf(math::TAU * frequency * n as f32 / fs) * 0.5).collect()
+    }
+
+    // Converts a tone and returns the levels at `frequency` and `alias` in
+    // the output relative to the input level. The analysis window is a
+    // whole number of cycles of both, at 10 Hz resolution.
+    fn measure(quality: Quality, fs_in: f32, fs_out: f32, frequency: f32, alias: f32) -> (f32, f32) {
+        let input = sine(fs_in, frequency, fs_in as usize);
+        let output = resample(quality, 1, fs_out / fs_in, &input);
+        let start = fs_out as usize / 4;
+        let window = &output[start..start + fs_out as usize / 10];
+        let level = |f: f32| math::gain_to_db(goertzel(window, fs_out, f) / 0.5);
+        (level(frequency), level(alias))
+    }
+
+    #[test]
+    fn aliasing() {
+        for &quality in QUALITIES.iter() {
+            // 20 kHz is above the 16 kHz output nyquist and folds to 12 kHz
+            let (_, down) = measure(quality, 48_000., 32_000., 20_000., 12_000.);
+            // the 34.1 kHz image of a 10 kHz tone folds to 13.9 kHz
+            let (_, up) = measure(quality, 44_100., 48_000., 10_000., 13_900.);
+            std::println!("api::resample {:?} aliasing 48 > 32 kHz: {:.1} dB, 44.1 > 48 kHz: {:.1} dB",
+                          quality, down, up);
+            // a few dB of slack on the figures documented for each quality
+            let limit = match quality {
+                Quality::Draft => -57.,
+                Quality::Normal => -87.,
+                Quality::High => -95.,
+            };
+            assert!(down < limit && up < limit);
+        }
+    }
+
+    #[test]
+    fn passband() {
+        for &quality in QUALITIES.iter() {
+            // highest frequency kept within 0.5 dB
+            let edge = match quality {
+                Quality::Draft => 10_000.,
+                Quality::Normal => 15_000.,
+                Quality::High => 18_000.,
+            };
+            let (gain, _) = measure(quality, 44_100., 48_000., 1_000., 2_000.);
+            let (high, _) = measure(quality, 44_100., 48_000., edge, 2_000.);
+            std::println!("api::resample {:?} passband gain 1 kHz: {:.3} dB, {} kHz: {:.3} dB",
+                          quality, gain, edge / 1000., high);
+            assert!(gain.abs() < 0.05);
+            assert!(high.abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn varying_ratio() {
+        // drift correction around 48 kHz, streamed in codec sized blocks
+        let fs = 48_000.;
+        let input = sine(fs, 1_000., 48_000);
+        let mut resampler = Resampler::new(Quality::Normal, 2, 1.);
+        let stereo: Vec<f32> = input.iter().flat_map(|&x| [x, x].to_vec()).collect();
+        let mut output = Vec::new();
+        let mut block = [0.; 2 * 64];
+        let mut position = 0;
+        let mut blocks = 0;
+        while position < stereo.len() {
+            resampler.set_ratio(1. + 0.005 * math::sinf(blocks as f32 * 0.05));
+            let (consumed, produced) = resampler.process(&stereo[position..], &mut block);
+            position += consumed * 2;
+            output.extend_from_slice(&block[..produced * 2]);
+            blocks += 1;
+        }
+        let frames = output.len() / 2;
+        assert!((frames as f32 - 48_000.).abs() < 48_000. * 0.006);
+        assert!(output.iter().all(|x| x.is_finite() && x.abs() < 0.51));
+        let start = resampler.latency() * 2;
+        let left: Vec<f32> = output[start..].iter().step_by(2).cloned().collect();
+        let right: Vec<f32> = output[start + 1..].iter().step_by(2).cloned().collect();
+        assert_eq!(left, right);
+    }
+}
@@ -0,0 +1,301 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::delay::DelayLine;
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+// Freeverb tunings, in samples at 44.1 kHz
+//
+// See: https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
+
+const TUNING_FS: f32 = 44_100.;
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 3.;
+const SCALE_DAMPING: f32 = 0.4;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+const MAX_PRE_DELAY: f32 = 0.1; // seconds
+
+
+// - reverb::Comb -------------------------------------------------------------
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Comb {
+        Comb {
+            buffer: vec![0.; length.max(1)],
+            index: 0,
+            store: 0.,
+        }
+    }
+
+    #[inline(always)]
+    fn tick(&mut self, x: f32, feedback: f32, damping: f32) -> f32 {
+        let y = self.buffer[self.index];
+        self.store = y + (self.store - y) * damping;
+        self.buffer[self.index] = x + self.store * feedback;
+        self.index += 1;
+        if self.index == self.buffer.len() {
+            self.index = 0;
+        }
+        y
+    }
+
+    fn clear(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0.;
+        }
+        self.store = 0.;
+    }
+}
+
+
+// - reverb::AllPass ----------------------------------------------------------
+
+struct AllPass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl AllPass {
+    fn new(length: usize) -> AllPass {
+        AllPass {
+            buffer: vec![0.; length.max(1)],
+            index: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn tick(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = x + delayed * ALLPASS_FEEDBACK;
+        self.index += 1;
+        if self.index == self.buffer.len() {
+            self.index = 0;
+        }
+        delayed - x
+    }
+
+    fn clear(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0.;
+        }
+    }
+}
+
+
+// - reverb::Reverb -----------------------------------------------------------
+
+// Freeverb style stereo reverb: a pre-delay feeding 8 parallel damped
+// comb filters followed by 4 series allpass filters per channel.
+//
+// Delay lengths are scaled from the original 44.1 kHz tunings so the
+// character is preserved at other sample rates.
+
+pub struct Reverb {
+    pub fs: f32,
+    pub size: f32,      // 0 - 1
+    pub damping: f32,   // 0 - 1
+    pub pre_delay: f32, // seconds
+    pub mix: f32,       // 0 - 1, dry / wet
+    pub width: f32,     // 0 - 1
+
+    feedback: f32,
+    damping_coefficient: f32,
+    pre_delay_samples: usize,
+    wet1: f32,
+    wet2: f32,
+    dry: f32,
+
+    pre_delay_line: DelayLine,
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<AllPass>; 2],
+}
+
+
+impl Reverb {
+    pub fn new(fs: f32) -> Reverb {
+        let scale = |length: usize| ((length as f32) * fs / TUNING_FS) as usize;
+        let spread = |offset: usize| {
+            (
+                COMB_TUNINGS.iter().map(|&length| Comb::new(scale(length + offset))).collect(),
+                ALLPASS_TUNINGS.iter().map(|&length| AllPass::new(scale(length + offset))).collect(),
+            )
+        };
+        let (combs_0, allpasses_0) = spread(0);
+        let (combs_1, allpasses_1) = spread(STEREO_SPREAD);
+
+        let mut reverb = Reverb {
+            fs: fs,
+            size: 0.5,
+            damping: 0.5,
+            pre_delay: 0.,
+            mix: 0.3,
+            width: 1.,
+            feedback: 0.,
+            damping_coefficient: 0.,
+            pre_delay_samples: 0,
+            wet1: 0.,
+            wet2: 0.,
+            dry: 0.,
+            pre_delay_line: DelayLine::new((MAX_PRE_DELAY * fs) as usize + 2),
+            combs: [combs_0, combs_1],
+            allpasses: [allpasses_0, allpasses_1],
+        };
+        reverb.update();
+        reverb
+    }
+
+    pub fn set_size(&mut self, size: f32) {
+        self.size = math::clamp(size, 0., 1.);
+        self.update();
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = math::clamp(damping, 0., 1.);
+        self.update();
+    }
+
+    pub fn set_pre_delay(&mut self, pre_delay: f32) {
+        self.pre_delay = math::clamp(pre_delay, 0., MAX_PRE_DELAY);
+        self.update();
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+        self.update();
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width = math::clamp(width, 0., 1.);
+        self.update();
+    }
+
+    pub fn reset(&mut self) {
+        self.pre_delay_line.clear();
+        for channel in 0..2 {
+            for comb in self.combs[channel].iter_mut() {
+                comb.clear();
+            }
+            for allpass in self.allpasses[channel].iter_mut() {
+                allpass.clear();
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        self.feedback = self.size * SCALE_ROOM + OFFSET_ROOM;
+        self.damping_coefficient = self.damping * SCALE_DAMPING;
+        self.pre_delay_samples = (self.pre_delay * self.fs) as usize;
+        let wet = self.mix * SCALE_WET;
+        self.wet1 = wet * (self.width / 2. + 0.5);
+        self.wet2 = wet * ((1. - self.width) / 2.);
+        self.dry = 1. - self.mix;
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let feedback = self.feedback;
+        let damping = self.damping_coefficient;
+        for frame in buffer.chunks_mut(num_channels) {
+            let x0 = frame[0];
+            let x1 = if num_channels > 1 { frame[1] } else { x0 };
+
+            // pre-delay
+            let x = (x0 + x1) * FIXED_GAIN;
+            let input = if self.pre_delay_samples > 0 {
+                self.pre_delay_line.read(self.pre_delay_samples)
+            } else {
+                x
+            };
+            self.pre_delay_line.write(x);
+
+            let mut out = [0.; 2];
+            for channel in 0..2 {
+                let mut y = 0.;
+                for comb in self.combs[channel].iter_mut() {
+                    y += comb.tick(input, feedback, damping);
+                }
+                for allpass in self.allpasses[channel].iter_mut() {
+                    y = allpass.tick(y);
+                }
+                out[channel] = y;
+            }
+
+            frame[0] = out[0] * self.wet1 + out[1] * self.wet2 + x0 * self.dry;
+            if num_channels > 1 {
+                frame[1] = out[1] * self.wet1 + out[0] * self.wet2 + x1 * self.dry;
+            }
+        }
+    }
+}
+
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    extern crate std;
+
+    use alloc::vec;
+
+    use crate::audio::Config;
+    use crate::driver::{Codec, simulator};
+    use super::Reverb;
+
+    // Reports the host cost of the reverb per block when driven by the
+    // simulated codec. Run with `--release --nocapture`, the numbers are
+    // for comparing changes against each other rather than an absolute
+    // measure of the ESP32 budget.
+    #[test]
+    fn benchmark() {
+        let config = Config {
+            fs: 48_000.,
+            num_channels: 2,
+            word_size: 2,
+            block_length: 256,
+        };
+        let driver = simulator::Driver::new();
+        let mut reverb = Reverb::new(config.fs);
+        reverb.set_size(0.8);
+        reverb.set_pre_delay(0.02);
+        let mut buffer = vec![0.; config.block_length];
+
+        let num_blocks: u32 = 2000;
+        let start = std::time::Instant::now();
+        for _ in 0..num_blocks {
+            driver.read(&config, &mut buffer).unwrap();
+            reverb.process(config.num_channels, &mut buffer);
+            driver.write(&config, &buffer).unwrap();
+        }
+        let per_block = start.elapsed() / num_blocks;
+
+        let num_frames = config.block_length / config.num_channels;
+        let budget = num_frames as f64 / config.fs as f64;
+        std::println!("api::reverb {} frames @ {} Hz: {:?} per block, {:.2}% of block period on host",
+                      num_frames, config.fs, per_block,
+                      100. * per_block.as_secs_f64() / budget);
+
+        assert_eq!(driver.frames_written(), num_blocks as usize * num_frames);
+        assert!(driver.peak().is_finite() && driver.peak() < 2.);
+    }
+}
@@ -0,0 +1,290 @@
+extern crate alloc;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::cell::RefCell;
+use core::ops::{Deref, DerefMut};
+
+use esp_idf::EspError;
+
+use crate::allocators::CapsBuffer;
+use crate::audio::Buffer;
+
+
+// - sample::Pcm --------------------------------------------------------------
+
+// Storage formats for sample data. 16 bit keeps flash and PSRAM use down
+// for long samples, f32 avoids the conversion.
+pub trait Pcm: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(x: f32) -> Self;
+}
+
+impl Pcm for f32 {
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    #[inline(always)]
+    fn from_f32(x: f32) -> f32 {
+        x
+    }
+}
+
+impl Pcm for i16 {
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self as f32 * (1. / 32768.)
+    }
+
+    #[inline(always)]
+    fn from_f32(x: f32) -> i16 {
+        let x = if x > 1. { 1. } else if x < -1. { -1. } else { x };
+        (x * 32767.) as i16
+    }
+}
+
+
+// - sample::Source -----------------------------------------------------------
+
+// Read side of sample storage shared by the sampler and granular engines.
+//
+// Frames are addressed by their position in the underlying storage, which
+// wraps at `capacity` while recording. The `len` valid frames start at
+// `offset`.
+//
+// A sample that is recorded into while it is shared is held in an
+// `Rc<RefCell<Sample>>`: readers take a clone of the `Rc` and the audio
+// closure records with `sample.borrow_mut().record(...)` before they run.
+
+pub trait Source {
+    fn fs(&self) -> f32;
+    fn num_channels(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn len(&self) -> usize;
+    fn offset(&self) -> usize;
+    fn frame(&self, index: usize, channel: usize) -> f32;
+
+    // true while recording wraps around and overwrites the oldest frames
+    fn circular(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    fn read_linear(&self, position: f32, channel: usize) -> f32 {
+        let capacity = self.capacity();
+        let index = position as usize;
+        let frac_part = position - index as f32;
+        let x0 = self.frame(index % capacity, channel);
+        let x1 = self.frame((index + 1) % capacity, channel);
+        x0 + (x1 - x0) * frac_part
+    }
+
+    #[inline(always)]
+    fn read_cubic(&self, position: f32, channel: usize) -> f32 {
+        let index = position as usize;
+        self.read_cubic_at(index, position - index as f32, channel)
+    }
+
+    // 4-point, 3rd-order hermite interpolation. Takes the integer and
+    // fractional parts separately so positions deep into long samples keep
+    // their precision.
+    #[inline(always)]
+    fn read_cubic_at(&self, index: usize, t: f32, channel: usize) -> f32 {
+        let capacity = self.capacity();
+        let xm1 = self.frame((index + capacity - 1) % capacity, channel);
+        let x0 = self.frame(index % capacity, channel);
+        let x1 = self.frame((index + 1) % capacity, channel);
+        let x2 = self.frame((index + 2) % capacity, channel);
+        let c1 = 0.5 * (x1 - xm1);
+        let c2 = xm1 - 2.5 * x0 + 2. * x1 - 0.5 * x2;
+        let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+        ((c3 * t + c2) * t + c1) * t + x0
+    }
+}
+
+impl<T: Source> Source for Rc<T> {
+    fn fs(&self) -> f32 { (**self).fs() }
+    fn num_channels(&self) -> usize { (**self).num_channels() }
+    fn capacity(&self) -> usize { (**self).capacity() }
+    fn len(&self) -> usize { (**self).len() }
+    fn offset(&self) -> usize { (**self).offset() }
+    fn circular(&self) -> bool { (**self).circular() }
+    #[inline(always)]
+    fn frame(&self, index: usize, channel: usize) -> f32 { (**self).frame(index, channel) }
+}
+
+impl<'a, T: Source> Source for &'a T {
+    fn fs(&self) -> f32 { (**self).fs() }
+    fn num_channels(&self) -> usize { (**self).num_channels() }
+    fn capacity(&self) -> usize { (**self).capacity() }
+    fn len(&self) -> usize { (**self).len() }
+    fn offset(&self) -> usize { (**self).offset() }
+    fn circular(&self) -> bool { (**self).circular() }
+    #[inline(always)]
+    fn frame(&self, index: usize, channel: usize) -> f32 { (**self).frame(index, channel) }
+}
+
+impl<T: Source> Source for RefCell<T> {
+    fn fs(&self) -> f32 { self.borrow().fs() }
+    fn num_channels(&self) -> usize { self.borrow().num_channels() }
+    fn capacity(&self) -> usize { self.borrow().capacity() }
+    fn len(&self) -> usize { self.borrow().len() }
+    fn offset(&self) -> usize { self.borrow().offset() }
+    fn circular(&self) -> bool { self.borrow().circular() }
+    #[inline(always)]
+    fn frame(&self, index: usize, channel: usize) -> f32 { self.borrow().frame(index, channel) }
+}
+
+
+// - sample::Sample -----------------------------------------------------------
+
+// Interleaved sample data over any backing store: a `Vec`, a PSRAM
+// `CapsBuffer` or a `&'static` slice in flash.
+//
+// `record` appends incoming audio, e.g. the input buffer handed to the
+// audio closure after `Codec::read`. Once full, recording either stops or,
+// with `circular` set, keeps overwriting the oldest frames.
+
+pub struct Sample<B = Vec<f32>> {
+    pub fs: f32,
+    pub num_channels: usize,
+    pub circular: bool,
+
+    data: B,
+    capacity: usize,
+    length: usize,
+    write_index: usize,
+}
+
+
+impl Sample<Vec<f32>> {
+    pub fn new(fs: f32, num_channels: usize, frames: usize) -> Sample<Vec<f32>> {
+        let num_channels = num_channels.max(1);
+        Sample::empty(fs, num_channels, vec![0.; frames.max(1) * num_channels])
+    }
+}
+
+
+impl Sample<CapsBuffer<f32>> {
+    // allocate the sample from external PSRAM
+    pub fn external(fs: f32, num_channels: usize, frames: usize) -> Result<Sample<CapsBuffer<f32>>, EspError> {
+        let num_channels = num_channels.max(1);
+        Ok(Sample::empty(fs, num_channels, CapsBuffer::external(frames.max(1) * num_channels)?))
+    }
+}
+
+
+impl<S: Pcm, B: Deref<Target = [S]>> Sample<B> {
+    // wrap existing interleaved data, e.g. a `&'static [i16]` in flash
+    pub fn from_data(fs: f32, num_channels: usize, data: B) -> Sample<B> {
+        let mut sample = Sample::empty(fs, num_channels, data);
+        sample.length = sample.capacity;
+        sample
+    }
+
+    // wrap storage to be recorded into
+    pub fn empty(fs: f32, num_channels: usize, data: B) -> Sample<B> {
+        let num_channels = num_channels.max(1);
+        let capacity = data.len() / num_channels;
+        Sample {
+            fs: fs,
+            num_channels: num_channels,
+            circular: false,
+            data: data,
+            capacity: capacity,
+            length: 0,
+            write_index: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[S] {
+        &self.data
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.length == self.capacity
+    }
+
+    // forget the recorded frames, the data itself is left in place
+    pub fn clear(&mut self) {
+        self.length = 0;
+        self.write_index = 0;
+    }
+
+    // frame index of the next write
+    pub fn write_index(&self) -> usize {
+        self.write_index
+    }
+}
+
+
+impl<S: Pcm, B: DerefMut<Target = [S]>> Sample<B> {
+    // Records an interleaved buffer with `num_channels` channels, mapping
+    // channels by index and repeating the last one if the sample has more.
+    // Returns the number of frames recorded.
+    pub fn record(&mut self, num_channels: usize, input: &Buffer) -> usize {
+        if num_channels == 0 || self.capacity == 0 {
+            return 0;
+        }
+        let mut count = 0;
+        for frame in input.chunks(num_channels) {
+            if self.write_index == self.capacity {
+                if !self.circular {
+                    break;
+                }
+                self.write_index = 0;
+            }
+            let base = self.write_index * self.num_channels;
+            for channel in 0..self.num_channels {
+                let x = frame[channel.min(frame.len() - 1)];
+                self.data[base + channel] = S::from_f32(x);
+            }
+            self.write_index += 1;
+            if self.length < self.capacity {
+                self.length += 1;
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+
+impl<S: Pcm, B: Deref<Target = [S]>> Source for Sample<B> {
+    fn fs(&self) -> f32 {
+        self.fs
+    }
+
+    fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    fn circular(&self) -> bool {
+        self.circular
+    }
+
+    fn offset(&self) -> usize {
+        if self.length < self.capacity || self.write_index == self.capacity {
+            0
+        } else {
+            self.write_index
+        }
+    }
+
+    #[inline(always)]
+    fn frame(&self, index: usize, channel: usize) -> f32 {
+        let channel = if channel < self.num_channels { channel } else { self.num_channels - 1 };
+        self.data[index * self.num_channels + channel].to_f32()
+    }
+}
@@ -0,0 +1,244 @@
+use crate::audio::Buffer;
+use crate::envelope::Envelope;
+use crate::math;
+use crate::sample::Source;
+use crate::voice::{Note, Voice};
+
+
+// - sampler::Mode ------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    OneShot,  // play from start to end, note off is ignored
+    Forward,  // loop between the loop points until released
+    PingPong, // loop back and forth between the loop points until released
+}
+
+
+// - sampler::Sampler ---------------------------------------------------------
+
+// Sample playback voice over any sample `Source`, wrap the `Sample` in an
+// `Rc` to share it between voices.
+//
+// Playback starts at `start` and stops at `end`, looping modes repeat
+// between `loop_start` and `loop_end` once reached. All points are frames
+// into the sample. The sample plays at its original pitch for `root`.
+
+pub struct Sampler<S> {
+    pub fs: f32,
+    pub source: S,
+    pub mode: Mode,
+    pub root: u8,
+    pub tune: f32, // semitones
+    pub envelope: Envelope,
+
+    start: usize,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+
+    index: usize,
+    fraction: f32,
+    increment: f32,
+    reverse: bool,
+    playing: bool,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl<S: Source> Sampler<S> {
+    pub fn new(fs: f32, source: S) -> Sampler<S> {
+        let length = source.len();
+        Sampler {
+            fs: fs,
+            source: source,
+            mode: Mode::OneShot,
+            root: 60,
+            tune: 0.,
+            envelope: Envelope::adsr(fs, 0.001, 0.1, 1., 0.05),
+            start: 0,
+            end: length,
+            loop_start: 0,
+            loop_end: length,
+            index: 0,
+            fraction: 0.,
+            increment: 1.,
+            reverse: false,
+            playing: false,
+            gain: 0.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    pub fn set_root(&mut self, root: u8) {
+        self.root = root;
+    }
+
+    pub fn set_tune(&mut self, tune: f32) {
+        self.tune = tune;
+    }
+
+    // playback region, loop points are kept inside it
+    pub fn set_region(&mut self, start: usize, end: usize) {
+        let length = self.source.len();
+        self.end = end.min(length);
+        self.start = start.min(self.end);
+        let (loop_start, loop_end) = (self.loop_start, self.loop_end);
+        self.set_loop(loop_start, loop_end);
+    }
+
+    pub fn set_loop(&mut self, loop_start: usize, loop_end: usize) {
+        self.loop_end = loop_end.max(self.start).min(self.end);
+        self.loop_start = loop_start.max(self.start).min(self.loop_end);
+    }
+
+    pub fn region(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+
+    pub fn loop_points(&self) -> (usize, usize) {
+        (self.loop_start, self.loop_end)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // a loop shorter than this plays as a one-shot
+    fn is_looping(&self) -> bool {
+        self.mode != Mode::OneShot && self.loop_end - self.loop_start >= 2
+    }
+
+    #[inline(always)]
+    fn advance(&mut self) {
+        if self.reverse {
+            self.fraction -= self.increment;
+            if self.fraction < 0. {
+                let whole = math::floorf(self.fraction);
+                let steps = (-whole) as usize;
+                self.fraction -= whole;
+                let room = self.index.saturating_sub(self.loop_start);
+                if steps > room {
+                    // reflect off the loop start
+                    let over = (steps - room) as f32 - self.fraction;
+                    self.reverse = false;
+                    self.index = self.loop_start;
+                    self.fraction = over;
+                    self.normalize();
+                } else {
+                    self.index -= steps;
+                }
+            }
+            return;
+        }
+
+        self.fraction += self.increment;
+        self.normalize();
+
+        if self.is_looping() {
+            match self.mode {
+                Mode::PingPong => {
+                    // reflect off the last frame of the loop, so reads stay
+                    // inside [loop_start, loop_end)
+                    let last = self.loop_end - 1;
+                    if self.index >= last {
+                        let over = (self.index - last) as f32 + self.fraction;
+                        self.reverse = true;
+                        self.index = last;
+                        self.fraction = -over;
+                        if self.fraction < 0. {
+                            let whole = math::floorf(self.fraction);
+                            self.index -= ((-whole) as usize).min(self.index - self.loop_start);
+                            self.fraction -= whole;
+                        }
+                    }
+                }
+                _ => {
+                    if self.index >= self.loop_end {
+                        let length = self.loop_end - self.loop_start;
+                        self.index = self.loop_start + (self.index - self.loop_end) % length;
+                    }
+                }
+            }
+        } else if self.index + 1 >= self.end {
+            self.playing = false;
+        }
+    }
+
+    #[inline(always)]
+    fn normalize(&mut self) {
+        if self.fraction >= 1. {
+            let whole = self.fraction as usize;
+            self.index += whole;
+            self.fraction -= whole as f32;
+        }
+    }
+}
+
+
+impl<S: Source> Voice for Sampler<S> {
+    fn note_on(&mut self, note: &Note, legato: bool) {
+        let semitones = note.pitch() - self.root as f32 + self.tune;
+        self.increment = math::powf(2., semitones / 12.) * self.source.fs() / self.fs;
+        self.gain = note.velocity * note.gain;
+        let (left, right) = math::pan_gains(note.pan);
+        self.left = left;
+        self.right = right;
+        if legato && self.playing {
+            return;
+        }
+        self.index = self.start;
+        self.fraction = 0.;
+        self.reverse = false;
+        self.playing = self.end > self.start + 1;
+        self.envelope.gate_on();
+    }
+
+    fn note_off(&mut self) {
+        if self.mode != Mode::OneShot {
+            self.envelope.gate_off();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.playing && self.envelope.is_active()
+    }
+
+    fn level(&self) -> f32 {
+        self.envelope.level() * self.gain
+    }
+
+    fn reset(&mut self) {
+        self.playing = false;
+        self.envelope.reset();
+    }
+
+    fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let stereo = self.source.num_channels() > 1;
+        for frame in buffer.chunks_mut(num_channels) {
+            if !self.playing {
+                break;
+            }
+            let gain = self.envelope.next() * self.gain;
+            let x0 = self.source.read_cubic_at(self.index, self.fraction, 0) * gain;
+            if num_channels > 1 {
+                let x1 = if stereo { self.source.read_cubic_at(self.index, self.fraction, 1) * gain } else { x0 };
+                frame[0] += x0 * self.right;
+                frame[1] += x1 * self.left;
+            } else {
+                frame[0] += x0;
+            }
+            self.advance();
+        }
+    }
+}
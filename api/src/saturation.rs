@@ -0,0 +1,441 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::math;
+
+
+// - global constants ---------------------------------------------------------
+
+// kaiser window beta for the half-band filters, ~80 dB stopband
+const KAISER_BETA: f32 = 8.;
+
+// taps per half-band branch for the first and second oversampling stages
+const STAGE_1_TAPS: usize = 24;
+const STAGE_2_TAPS: usize = 12;
+
+// asymmetric tube bias
+const TUBE_BIAS: f32 = 0.3;
+
+
+// - types --------------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shape {
+    Tanh,
+    SoftClip,
+    HardClip,
+    Foldback,
+    Tube,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Factor {
+    X1,
+    X2,
+    X4,
+}
+
+
+// - waveshapers --------------------------------------------------------------
+
+#[inline(always)]
+pub fn shape(shape: Shape, x: f32) -> f32 {
+    match shape {
+        Shape::Tanh => math::tanhf(x),
+        Shape::SoftClip => {
+            // cubic, a slope of 1.5 at the origin and flat at |x| = 1
+            let x = math::clamp(x, -1., 1.);
+            x * (1.5 - 0.5 * x * x)
+        }
+        Shape::HardClip => math::clamp(x, -1., 1.),
+        Shape::Foldback => {
+            // triangle fold, reflects back down every time |x| crosses 1
+            let t = (x + 1.) * 0.25;
+            let t = t - math::floorf(t);
+            1. - math::fabsf(t * 4. - 2.)
+        }
+        Shape::Tube => {
+            // biased tanh, asymmetric so it adds even harmonics
+            math::tanhf(x + TUBE_BIAS) - math::tanhf(TUBE_BIAS)
+        }
+    }
+}
+
+
+// - saturation::HalfBand -----------------------------------------------------
+
+// Linear phase half-band FIR split into its two polyphase branches.
+//
+// Every other tap of a half-band filter is zero apart from the centre
+// tap of 0.5, so one branch is a plain delay and the other an FIR of
+// `2 * taps` coefficients. Upsampling and downsampling by 2 therefore
+// cost `2 * taps` multiplies per input or output sample.
+
+#[derive(Clone)]
+pub struct HalfBand {
+    coefficients: Vec<f32>,
+
+    // upsampler
+    up_history: Vec<f32>,
+    up_index: usize,
+
+    // downsampler
+    down_even: Vec<f32>,
+    down_odd: Vec<f32>,
+    down_index: usize,
+}
+
+
+impl HalfBand {
+    pub fn new(taps: usize) -> HalfBand {
+        let coefficients = design(if taps < 1 { 1 } else { taps });
+        let len = coefficients.len();
+        HalfBand {
+            coefficients: coefficients,
+            up_history: vec![0.; len],
+            up_index: 0,
+            down_even: vec![0.; len],
+            down_odd: vec![0.; len],
+            down_index: 0,
+        }
+    }
+
+    // latency in samples at the lower rate for an up/down round trip
+    pub fn latency(&self) -> usize {
+        self.coefficients.len() - 1
+    }
+
+    pub fn reset(&mut self) {
+        for x in self.up_history.iter_mut() { *x = 0.; }
+        for x in self.down_even.iter_mut() { *x = 0.; }
+        for x in self.down_odd.iter_mut() { *x = 0.; }
+        self.up_index = 0;
+        self.down_index = 0;
+    }
+
+    #[inline(always)]
+    fn convolve(coefficients: &[f32], history: &[f32], index: usize) -> f32 {
+        // history[index] is the newest sample
+        let len = history.len();
+        let mut sum = 0.;
+        let mut h = index;
+        for &c in coefficients.iter() {
+            sum += c * history[h];
+            h = if h == 0 { len - 1 } else { h - 1 };
+        }
+        sum
+    }
+
+    #[inline(always)]
+    pub fn upsample(&mut self, x: f32) -> (f32, f32) {
+        let len = self.up_history.len();
+        self.up_index = if self.up_index + 1 == len { 0 } else { self.up_index + 1 };
+        self.up_history[self.up_index] = x;
+
+        let even = 2. * HalfBand::convolve(&self.coefficients, &self.up_history, self.up_index);
+        let centre = len / 2 - 1;
+        let odd = self.up_history[(self.up_index + len - centre) % len];
+        (even, odd)
+    }
+
+    #[inline(always)]
+    pub fn downsample(&mut self, even: f32, odd: f32) -> f32 {
+        let len = self.down_even.len();
+        self.down_index = if self.down_index + 1 == len { 0 } else { self.down_index + 1 };
+        self.down_even[self.down_index] = even;
+
+        let centre = len / 2;
+        let y = HalfBand::convolve(&self.coefficients, &self.down_even, self.down_index)
+              + 0.5 * self.down_odd[(self.down_index + len - centre) % len];
+        self.down_odd[self.down_index] = odd;
+        y
+    }
+}
+
+// even taps h[2j] of a kaiser windowed half-band lowpass with 4 * taps - 1
+// taps in total, the odd taps are all zero except for the centre
+fn design(taps: usize) -> Vec<f32> {
+    let n = 4 * taps - 1;
+    let centre = (n - 1) as f32 / 2.;
+    let i0_beta = math::bessel_i0(KAISER_BETA);
+    let mut coefficients = Vec::with_capacity(taps * 2);
+    for j in 0..taps * 2 {
+        let k = (2 * j) as f32;
+        let t = k - centre; // odd, never zero
+        let sinc = math::sinf(math::PI * t / 2.) / (math::PI * t);
+        let r = (k - centre) / centre;
+        let window = math::bessel_i0(KAISER_BETA * math::sqrtf(1. - r * r)) / i0_beta;
+        coefficients.push(sinc * window);
+    }
+    coefficients
+}
+
+
+// - saturation::Oversampler --------------------------------------------------
+
+// Runs a per-sample non-linearity at 2x or 4x the sample rate using
+// cascaded half-band stages.
+
+pub struct Oversampler {
+    pub factor: Factor,
+
+    stage_1: Vec<HalfBand>,
+    stage_2: Vec<HalfBand>,
+}
+
+
+impl Oversampler {
+    pub fn new(factor: Factor, num_channels: usize) -> Oversampler {
+        let stage_1 = HalfBand::new(STAGE_1_TAPS);
+        let stage_2 = HalfBand::new(STAGE_2_TAPS);
+        Oversampler {
+            factor: factor,
+            stage_1: vec![stage_1; num_channels],
+            stage_2: vec![stage_2; num_channels],
+        }
+    }
+
+    pub fn latency(&self) -> usize {
+        match self.factor {
+            Factor::X1 => 0,
+            Factor::X2 => self.stage_1[0].latency(),
+            Factor::X4 => self.stage_1[0].latency() + (self.stage_2[0].latency() + 1) / 2,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stage_1.iter_mut() {
+            stage.reset();
+        }
+        for stage in self.stage_2.iter_mut() {
+            stage.reset();
+        }
+    }
+
+    #[inline(always)]
+    pub fn tick<F: FnMut(f32) -> f32>(&mut self, channel: usize, x: f32, f: &mut F) -> f32 {
+        match self.factor {
+            Factor::X1 => f(x),
+            Factor::X2 => {
+                let stage = &mut self.stage_1[channel];
+                let (a, b) = stage.upsample(x);
+                stage.downsample(f(a), f(b))
+            }
+            Factor::X4 => {
+                let stage_1 = &mut self.stage_1[channel];
+                let stage_2 = &mut self.stage_2[channel];
+                let (a, b) = stage_1.upsample(x);
+                let (a0, a1) = stage_2.upsample(a);
+                let (b0, b1) = stage_2.upsample(b);
+                let a = stage_2.downsample(f(a0), f(a1));
+                let b = stage_2.downsample(f(b0), f(b1));
+                stage_1.downsample(a, b)
+            }
+        }
+    }
+
+    pub fn process<F: FnMut(f32) -> f32>(&mut self, num_channels: usize, buffer: &mut Buffer, mut f: F) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.stage_1.len());
+        for frame in buffer.chunks_mut(num_channels) {
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                *sample = self.tick(channel, *sample, &mut f);
+            }
+        }
+    }
+}
+
+
+// - saturation::Saturator ----------------------------------------------------
+
+pub struct Saturator {
+    pub shape: Shape,
+    pub drive: f32,  // dB
+    pub output: f32, // dB
+    pub mix: f32,    // 0 - 1, dry / wet
+
+    drive_gain: f32,
+    output_gain: f32,
+    oversampler: Oversampler,
+    dc_x: Vec<f32>,
+    dc_y: Vec<f32>,
+    dc_coefficient: f32,
+}
+
+
+impl Saturator {
+    pub fn new(fs: f32, shape: Shape, factor: Factor, num_channels: usize) -> Saturator {
+        Saturator {
+            shape: shape,
+            drive: 0.,
+            output: 0.,
+            mix: 1.,
+            drive_gain: 1.,
+            output_gain: 1.,
+            oversampler: Oversampler::new(factor, num_channels),
+            dc_x: vec![0.; num_channels],
+            dc_y: vec![0.; num_channels],
+            dc_coefficient: 1. - (math::TAU * 10. / fs),
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        self.shape = shape;
+    }
+
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive;
+        self.drive_gain = math::db_to_gain(drive);
+    }
+
+    pub fn set_output(&mut self, output: f32) {
+        self.output = output;
+        self.output_gain = math::db_to_gain(output);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = math::clamp(mix, 0., 1.);
+    }
+
+    // note that the dry signal is not delayed to match
+    pub fn latency(&self) -> usize {
+        self.oversampler.latency()
+    }
+
+    pub fn reset(&mut self) {
+        self.oversampler.reset();
+        for x in self.dc_x.iter_mut() { *x = 0.; }
+        for y in self.dc_y.iter_mut() { *y = 0.; }
+    }
+
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        let count = num_channels.min(self.dc_x.len());
+        let kind = self.shape;
+        let drive = self.drive_gain;
+        let wet = self.mix * self.output_gain;
+        let dry = 1. - self.mix;
+        let mut f = |x: f32| shape(kind, x * drive);
+        for frame in buffer.chunks_mut(num_channels) {
+            for (channel, sample) in frame.iter_mut().take(count).enumerate() {
+                let x = *sample;
+                let y = self.oversampler.tick(channel, x, &mut f);
+
+                // remove the dc offset introduced by asymmetric shapes
+                let dc = y - self.dc_x[channel] + self.dc_coefficient * self.dc_y[channel];
+                self.dc_x[channel] = y;
+                self.dc_y[channel] = dc;
+
+                *sample = x * dry + dc * wet;
+            }
+        }
+    }
+}
+
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    extern crate std;
+
+    use alloc::vec::Vec;
+
+    use crate::math;
+    use super::{Factor, Oversampler, Shape, shape};
+
+    const FS: f32 = 48_000.;
+    const LENGTH: usize = 4800; // 10 Hz bins
+
+    // magnitude of a single dft bin
+    fn goertzel(x: &[f32], frequency: f32) -> f32 {
+        let w = math::TAU * frequency / FS;
+        let coefficient = 2. * math::cosf(w);
+        let (mut s1, mut s2) = (0., 0.);
+        for &sample in x.iter() {
+            let s0 = sample + coefficient * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+        2. * math::sqrtf(power) / x.len() as f32
+    }
+
+    fn render(factor: Factor, kind: Shape, frequency: f32, drive: f32) -> Vec<f32> {
+        let mut oversampler = Oversampler::new(factor, 1);
+        let mut f = |x: f32| shape(kind, x * drive);
+        let warmup = 512;
+        let mut output = Vec::with_capacity(LENGTH);
+        for n in 0..(LENGTH + warmup) {
+            let x = math::sinf(math::TAU * frequency * n as f32 / FS) * 0.9;
+            let y = oversampler.tick(0, x, &mut f);
+            if n >= warmup {
+                output.push(y);
+            }
+        }
+        output
+    }
+
+    fn db(x: f32) -> f32 {
+        math::gain_to_db(x)
+    }
+
+    #[test]
+    fn thd() {
+        // 1 kHz through tanh: odd harmonics only, 3rd dominant
+        let y = render(Factor::X4, Shape::Tanh, 1000., 2.);
+        let fundamental = goertzel(&y, 1000.);
+        let h2 = goertzel(&y, 2000.);
+        let h3 = goertzel(&y, 3000.);
+        let h5 = goertzel(&y, 5000.);
+        let thd = math::sqrtf(h2 * h2 + h3 * h3 + h5 * h5) / fundamental;
+        std::println!("api::saturation tanh thd: {:.2}% h2: {:.1} dB h3: {:.1} dB",
+                      thd * 100., db(h2 / fundamental), db(h3 / fundamental));
+        assert!(thd > 0.01 && thd < 0.5);
+        assert!(db(h2 / fundamental) < -60.);
+
+        // tube shape is asymmetric and should add a 2nd harmonic
+        let y = render(Factor::X4, Shape::Tube, 1000., 2.);
+        let h2 = goertzel(&y, 2000.) / goertzel(&y, 1000.);
+        std::println!("api::saturation tube h2: {:.1} dB", db(h2));
+        assert!(db(h2) > -40.);
+    }
+
+    #[test]
+    fn aliasing() {
+        // the 7th and 9th harmonics of 5 kHz fold back to 13 kHz and 3 kHz
+        let frequency = 5000.;
+        let measure = |factor| {
+            let y = render(factor, Shape::Tanh, frequency, 4.);
+            let fundamental = goertzel(&y, frequency);
+            let alias = goertzel(&y, 13_000.).max(goertzel(&y, 3000.));
+            db(alias / fundamental)
+        };
+        let x1 = measure(Factor::X1);
+        let x2 = measure(Factor::X2);
+        let x4 = measure(Factor::X4);
+        std::println!("api::saturation aliasing x1: {:.1} dB x2: {:.1} dB x4: {:.1} dB", x1, x2, x4);
+        assert!(x2 < x1 - 20.);
+        assert!(x4 < x1 - 40.);
+    }
+
+    #[test]
+    fn passband() {
+        // a linear round trip through the oversampler should be transparent
+        for &factor in [Factor::X2, Factor::X4].iter() {
+            for &frequency in [1000., 15_000.].iter() {
+                let y = render(factor, Shape::HardClip, frequency, 0.5);
+                let gain = goertzel(&y, frequency) / (0.9 * 0.5);
+                std::println!("api::saturation {:?} passband gain at {} Hz: {:.3} dB", factor, frequency, db(gain));
+                assert!(math::fabsf(db(gain)) < 0.1);
+            }
+        }
+    }
+}
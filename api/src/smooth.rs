@@ -0,0 +1,265 @@
+use crate::math;
+
+
+// - smooth::OnePole ----------------------------------------------------------
+
+// Exponential approach to the target with a time constant in seconds.
+
+#[derive(Copy, Clone, Debug)]
+pub struct OnePole {
+    pub fs: f32,
+    pub time: f32,
+
+    value: f32,
+    target: f32,
+    coefficient: f32,
+}
+
+impl OnePole {
+    pub fn new(fs: f32, time: f32, value: f32) -> OnePole {
+        let mut smoother = OnePole {
+            fs: fs,
+            time: time,
+            value: value,
+            target: value,
+            coefficient: 0.,
+        };
+        smoother.set_time(time);
+        smoother
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+        self.coefficient = if time <= 0. {
+            1.
+        } else {
+            1. - math::expf(-1. / (time * self.fs))
+        };
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        math::fabsf(self.target - self.value) > 1e-6
+    }
+
+    // jump to the current target, e.g. on preset load
+    pub fn snap(&mut self) {
+        self.value = self.target;
+    }
+
+    pub fn snap_to(&mut self, value: f32) {
+        self.target = value;
+        self.value = value;
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        self.value += (self.target - self.value) * self.coefficient;
+        self.value
+    }
+
+    // advance by `frames` samples in one step, for control-rate use
+    pub fn advance(&mut self, frames: usize) -> f32 {
+        let decay = math::powf(1. - self.coefficient, frames as f32);
+        self.value = self.target + (self.value - self.target) * decay;
+        self.value
+    }
+
+    pub fn process(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = self.next();
+        }
+    }
+}
+
+
+// - smooth::Linear -----------------------------------------------------------
+
+// Constant-rate ramp that reaches the target in exactly `length` samples.
+
+#[derive(Copy, Clone, Debug)]
+pub struct Linear {
+    pub length: usize,
+
+    value: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+}
+
+impl Linear {
+    pub fn new(length: usize, value: f32) -> Linear {
+        Linear {
+            length: length,
+            value: value,
+            target: value,
+            step: 0.,
+            remaining: 0,
+        }
+    }
+
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+        if self.length == 0 {
+            self.snap();
+            return;
+        }
+        self.step = (target - self.value) / self.length as f32;
+        self.remaining = self.length;
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn snap(&mut self) {
+        self.value = self.target;
+        self.remaining = 0;
+    }
+
+    pub fn snap_to(&mut self, value: f32) {
+        self.target = value;
+        self.snap();
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 { self.target } else { self.value + self.step };
+        }
+        self.value
+    }
+
+    pub fn advance(&mut self, frames: usize) -> f32 {
+        if frames >= self.remaining {
+            self.snap();
+        } else {
+            self.remaining -= frames;
+            self.value += self.step * frames as f32;
+        }
+        self.value
+    }
+
+    pub fn process(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = self.next();
+        }
+    }
+}
+
+
+// - smooth::Multiplicative ---------------------------------------------------
+
+// Ramp with a constant ratio per sample so the change is linear in dB or
+// octaves. Reaches the target in exactly `length` samples. Values must be
+// greater than zero, anything smaller is clamped to `MINIMUM`.
+
+#[derive(Copy, Clone, Debug)]
+pub struct Multiplicative {
+    pub length: usize,
+
+    value: f32,
+    target: f32,
+    ratio: f32,
+    remaining: usize,
+}
+
+impl Multiplicative {
+    pub const MINIMUM: f32 = 1e-5;
+
+    pub fn new(length: usize, value: f32) -> Multiplicative {
+        let value = if value < Self::MINIMUM { Self::MINIMUM } else { value };
+        Multiplicative {
+            length: length,
+            value: value,
+            target: value,
+            ratio: 1.,
+            remaining: 0,
+        }
+    }
+
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = if target < Self::MINIMUM { Self::MINIMUM } else { target };
+        if self.length == 0 {
+            self.snap();
+            return;
+        }
+        self.ratio = math::powf(self.target / self.value, 1. / self.length as f32);
+        self.remaining = self.length;
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn snap(&mut self) {
+        self.value = self.target;
+        self.remaining = 0;
+    }
+
+    pub fn snap_to(&mut self, value: f32) {
+        self.target = if value < Self::MINIMUM { Self::MINIMUM } else { value };
+        self.snap();
+    }
+
+    #[inline(always)]
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 { self.target } else { self.value * self.ratio };
+        }
+        self.value
+    }
+
+    pub fn advance(&mut self, frames: usize) -> f32 {
+        if frames >= self.remaining {
+            self.snap();
+        } else {
+            self.remaining -= frames;
+            self.value *= math::powf(self.ratio, frames as f32);
+        }
+        self.value
+    }
+
+    pub fn process(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = self.next();
+        }
+    }
+}
@@ -0,0 +1,416 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::audio::Buffer;
+use crate::envelope::Envelope;
+use crate::math;
+use crate::smooth;
+use crate::wavetable;
+
+
+// - voice::Voice -------------------------------------------------------------
+
+// A note as handed to a voice by the allocator. `detune` and `pan` are
+// set by unison, `gain` compensates for the number of stacked voices.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Note {
+    pub note: u8,
+    pub velocity: f32, // 0 - 1
+    pub detune: f32,   // semitones
+    pub pan: f32,      // -1 left, 1 right
+    pub gain: f32,
+}
+
+impl Note {
+    pub fn new(note: u8, velocity: f32) -> Note {
+        Note {
+            note: note,
+            velocity: velocity,
+            detune: 0.,
+            pan: 0.,
+            gain: 1.,
+        }
+    }
+
+    // fractional midi note number
+    pub fn pitch(&self) -> f32 {
+        self.note as f32 + self.detune
+    }
+}
+
+// Anything the allocator can play.
+//
+// `note_on` with `legato` set changes pitch without retriggering, `render`
+// sums the voice into an interleaved buffer rather than overwriting it.
+pub trait Voice {
+    fn note_on(&mut self, note: &Note, legato: bool);
+    fn note_off(&mut self);
+    fn is_active(&self) -> bool;
+    fn level(&self) -> f32;
+    fn reset(&mut self);
+    fn render(&mut self, num_channels: usize, buffer: &mut Buffer);
+}
+
+
+// - voice::Voices ------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    Poly,
+    Mono,   // single note, retriggers on every note on
+    Legato, // single note, overlapping notes glide without retriggering
+}
+
+// which voice to take over when all of them are busy
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Stealing {
+    Oldest,
+    Quietest,
+    SameNote, // reuse the voice already playing the note, else the oldest
+}
+
+struct Slot<V> {
+    voice: V,
+    note: u8,
+    gate: bool,
+    age: u32,
+}
+
+// Note on / off to voice allocation for a fixed pool of voices.
+//
+// In `Poly` mode every note takes `unison` voices from the pool. In `Mono`
+// and `Legato` modes the first `unison` voices play the most recent held
+// note, releasing it returns to the previous one still held.
+
+pub struct Voices<V> {
+    pub mode: Mode,
+    pub stealing: Stealing,
+    pub unison: usize,
+    pub detune: f32, // cents, total spread of the unison stack
+    pub spread: f32, // 0 - 1, stereo spread of the unison stack
+
+    slots: Vec<Slot<V>>,
+    held: Vec<(u8, f32)>, // note, velocity
+    age: u32,
+}
+
+
+impl<V: Voice> Voices<V> {
+    pub fn new(voices: Vec<V>) -> Voices<V> {
+        let num_voices = voices.len();
+        Voices {
+            mode: Mode::Poly,
+            stealing: Stealing::Oldest,
+            unison: 1,
+            detune: 0.,
+            spread: 0.,
+            slots: voices.into_iter().map(|voice| Slot {
+                voice: voice,
+                note: 0,
+                gate: false,
+                age: 0,
+            }).collect(),
+            held: Vec::with_capacity(num_voices.max(16)),
+            age: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode != self.mode {
+            self.all_notes_off();
+            self.mode = mode;
+        }
+    }
+
+    pub fn set_stealing(&mut self, stealing: Stealing) {
+        self.stealing = stealing;
+    }
+
+    pub fn set_unison(&mut self, unison: usize, detune: f32, spread: f32) {
+        let unison = if unison < 1 { 1 } else if unison > self.slots.len() { self.slots.len() } else { unison };
+        if unison != self.unison {
+            self.all_notes_off();
+        }
+        self.unison = unison;
+        self.detune = detune;
+        self.spread = math::clamp(spread, 0., 1.);
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.voice.is_active()).count()
+    }
+
+    // for changing parameters across the whole pool
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.slots.iter_mut().map(|slot| &mut slot.voice)
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: f32) {
+        if velocity <= 0. {
+            self.note_off(note);
+            return;
+        }
+        self.age = self.age.wrapping_add(1);
+        match self.mode {
+            Mode::Poly => self.poly_note_on(note, velocity),
+            Mode::Mono | Mode::Legato => {
+                let legato = self.mode == Mode::Legato && !self.held.is_empty();
+                self.held.retain(|&(held, _)| held != note);
+                self.held.push((note, velocity));
+                self.play_mono(note, velocity, legato);
+            }
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        match self.mode {
+            Mode::Poly => {
+                for slot in self.slots.iter_mut() {
+                    if slot.gate && slot.note == note {
+                        slot.gate = false;
+                        slot.voice.note_off();
+                    }
+                }
+            }
+            Mode::Mono | Mode::Legato => {
+                let was_playing = self.held.last().map(|&(held, _)| held) == Some(note);
+                self.held.retain(|&(held, _)| held != note);
+                if !was_playing {
+                    return;
+                }
+                match self.held.last() {
+                    Some(&(previous, velocity)) => {
+                        let legato = self.mode == Mode::Legato;
+                        self.play_mono(previous, velocity, legato);
+                    }
+                    None => {
+                        for slot in self.slots.iter_mut().take(self.unison) {
+                            slot.gate = false;
+                            slot.voice.note_off();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // release everything, voices still run their release stage
+    pub fn all_notes_off(&mut self) {
+        self.held.clear();
+        for slot in self.slots.iter_mut() {
+            if slot.gate {
+                slot.gate = false;
+                slot.voice.note_off();
+            }
+        }
+    }
+
+    // silence everything immediately
+    pub fn reset(&mut self) {
+        self.held.clear();
+        for slot in self.slots.iter_mut() {
+            slot.gate = false;
+            slot.voice.reset();
+        }
+    }
+
+    // sum all active voices into `buffer`
+    pub fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        for slot in self.slots.iter_mut() {
+            if slot.voice.is_active() {
+                slot.voice.render(num_channels, buffer);
+            }
+        }
+    }
+
+    // replace the contents of `buffer` with the voices
+    pub fn process(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        for sample in buffer.iter_mut() {
+            *sample = 0.;
+        }
+        self.render(num_channels, buffer);
+    }
+
+    fn unison_note(&self, note: u8, velocity: f32, index: usize) -> Note {
+        let offset = if self.unison > 1 {
+            (index as f32 / (self.unison - 1) as f32) * 2. - 1.
+        } else {
+            0.
+        };
+        Note {
+            note: note,
+            velocity: velocity,
+            detune: offset * self.detune * 0.5 / 100.,
+            pan: offset * self.spread,
+            gain: 1. / math::sqrtf(self.unison as f32),
+        }
+    }
+
+    fn play_mono(&mut self, note: u8, velocity: f32, legato: bool) {
+        for index in 0..self.unison {
+            let event = self.unison_note(note, velocity, index);
+            let slot = &mut self.slots[index];
+            slot.voice.note_on(&event, legato);
+            slot.note = note;
+            slot.gate = true;
+            slot.age = self.age;
+        }
+    }
+
+    fn poly_note_on(&mut self, note: u8, velocity: f32) {
+        for index in 0..self.unison {
+            let slot_index = match self.find_slot(note) {
+                Some(slot_index) => slot_index,
+                None => return,
+            };
+            let event = self.unison_note(note, velocity, index);
+            let slot = &mut self.slots[slot_index];
+            slot.voice.note_on(&event, false);
+            slot.note = note;
+            slot.gate = true;
+            slot.age = self.age;
+        }
+    }
+
+    // Picks a voice for `note` skipping those already claimed by this
+    // note on. Idle voices come first, then released ones, then held ones.
+    fn find_slot(&self, note: u8) -> Option<usize> {
+        let age = self.age;
+        let candidates = || {
+            self.slots.iter().enumerate().filter(move |(_, slot)| slot.age != age || !slot.gate)
+        };
+
+        if self.stealing == Stealing::SameNote {
+            let same = candidates().find(|(_, slot)| {
+                slot.note == note && (slot.gate || slot.voice.is_active())
+            });
+            if let Some((index, _)) = same {
+                return Some(index);
+            }
+        }
+
+        if let Some((index, _)) = candidates().find(|(_, slot)| !slot.gate && !slot.voice.is_active()) {
+            return Some(index);
+        }
+
+        for &gate in [false, true].iter() {
+            let steal = match self.stealing {
+                Stealing::Oldest | Stealing::SameNote => candidates()
+                    .filter(|(_, slot)| slot.gate == gate)
+                    .max_by_key(|(_, slot)| age.wrapping_sub(slot.age)),
+                Stealing::Quietest => candidates()
+                    .filter(|(_, slot)| slot.gate == gate)
+                    .min_by(|(_, a), (_, b)| {
+                        a.voice.level().partial_cmp(&b.voice.level()).unwrap_or(core::cmp::Ordering::Equal)
+                    }),
+            };
+            if let Some((index, _)) = steal {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+}
+
+
+// - voice::WavetableVoice ----------------------------------------------------
+
+// Basic voice: a wavetable oscillator through an adsr, panned with the
+// unison spread. Pitch glides over `glide` seconds on legato notes.
+
+pub struct WavetableVoice {
+    pub fs: f32,
+    pub table: &'static [f32; wavetable::LENGTH],
+    pub envelope: Envelope,
+
+    pitch: smooth::OnePole, // midi note
+    phase: f32,
+    gain: f32,
+    left: f32,
+    right: f32,
+}
+
+
+impl WavetableVoice {
+    pub fn new(fs: f32, table: &'static [f32; wavetable::LENGTH]) -> WavetableVoice {
+        WavetableVoice {
+            fs: fs,
+            table: table,
+            envelope: Envelope::adsr(fs, 0.005, 0.1, 0.7, 0.2),
+            pitch: smooth::OnePole::new(fs, 0., 69.),
+            phase: 0.,
+            gain: 0.,
+            left: 1.,
+            right: 1.,
+        }
+    }
+
+    // seconds
+    pub fn set_glide(&mut self, glide: f32) {
+        self.pitch.set_time(glide);
+    }
+}
+
+
+impl Voice for WavetableVoice {
+    fn note_on(&mut self, note: &Note, legato: bool) {
+        self.gain = note.velocity * note.gain;
+        let (left, right) = math::pan_gains(note.pan);
+        self.left = left;
+        self.right = right;
+        if legato {
+            self.pitch.set_target(note.pitch());
+        } else {
+            self.pitch.snap_to(note.pitch());
+            self.envelope.gate_on();
+        }
+    }
+
+    fn note_off(&mut self) {
+        self.envelope.gate_off();
+    }
+
+    fn is_active(&self) -> bool {
+        self.envelope.is_active()
+    }
+
+    fn level(&self) -> f32 {
+        self.envelope.level() * self.gain
+    }
+
+    fn reset(&mut self) {
+        self.envelope.reset();
+        self.phase = 0.;
+    }
+
+    fn render(&mut self, num_channels: usize, buffer: &mut Buffer) {
+        if num_channels == 0 {
+            return;
+        }
+        // pitch is updated once per block
+        let dx = math::note_to_frequency(self.pitch.value()) / self.fs;
+        self.pitch.advance(buffer.len() / num_channels);
+
+        for frame in buffer.chunks_mut(num_channels) {
+            let y = wavetable::lookup(self.table, self.phase) * self.envelope.next() * self.gain;
+            self.phase += dx;
+            if self.phase >= 1. {
+                self.phase -= 1.;
+            }
+            if num_channels > 1 {
+                frame[0] += y * self.right;
+                frame[1] += y * self.left;
+            } else {
+                frame[0] += y;
+            }
+        }
+    }
+}
@@ -0,0 +1,818 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf::bindings as idf;
+use esp_idf::EspError;
+
+use crate::sample::Pcm;
+
+
+// - global constants ---------------------------------------------------------
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+const RIFF_HEADER_SIZE: usize = 12;
+const FMT_SIZE: usize = 16;
+const DATA_SIZE_OFFSET: usize = RIFF_HEADER_SIZE + 8 + FMT_SIZE + 4;
+const SMPL_HEADER_SIZE: usize = 36;
+const SMPL_LOOP_SIZE: usize = 24;
+const MAX_CHUNK_SIZE: usize = 64 * 1024; // bytes, largest fmt or smpl chunk the Reader will buffer
+
+const SCRATCH_SIZE: usize = 240; // bytes, a whole number of 1, 2, 3, 4 and 8 byte samples
+
+
+// - wav::Format --------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    Pcm8, // unsigned
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+    Float64,
+}
+
+
+impl Format {
+    fn from_header(tag: u16, bits: u16) -> Option<Format> {
+        match (tag, bits) {
+            (FORMAT_PCM, 8) => Some(Format::Pcm8),
+            (FORMAT_PCM, 16) => Some(Format::Pcm16),
+            (FORMAT_PCM, 24) => Some(Format::Pcm24),
+            (FORMAT_PCM, 32) => Some(Format::Pcm32),
+            (FORMAT_FLOAT, 32) => Some(Format::Float32),
+            (FORMAT_FLOAT, 64) => Some(Format::Float64),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u16 {
+        match self {
+            Format::Float32 | Format::Float64 => FORMAT_FLOAT,
+            _ => FORMAT_PCM,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            Format::Pcm8 => 1,
+            Format::Pcm16 => 2,
+            Format::Pcm24 => 3,
+            Format::Pcm32 | Format::Float32 => 4,
+            Format::Float64 => 8,
+        }
+    }
+
+    #[inline(always)]
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            Format::Pcm8 => (bytes[0] as f32 - 128.) * (1. / 128.),
+            Format::Pcm16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 * (1. / 32768.),
+            Format::Pcm24 => {
+                let x = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                x as f32 * (1. / 8_388_608.)
+            }
+            Format::Pcm32 => {
+                let x = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                x as f32 * (1. / 2_147_483_648.)
+            }
+            Format::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            Format::Float64 => {
+                let mut array = [0; 8];
+                array.copy_from_slice(&bytes[..8]);
+                f64::from_le_bytes(array) as f32
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn encode(&self, x: f32, bytes: &mut [u8]) {
+        let clamped = if x > 1. { 1. } else if x < -1. { -1. } else { x };
+        match self {
+            Format::Pcm8 => bytes[0] = ((clamped * 127.) as i32 + 128) as u8,
+            Format::Pcm16 => bytes[..2].copy_from_slice(&((clamped * 32_767.) as i16).to_le_bytes()),
+            Format::Pcm24 => {
+                let x = (clamped * 8_388_607.) as i32;
+                bytes[..3].copy_from_slice(&x.to_le_bytes()[..3]);
+            }
+            Format::Pcm32 => {
+                let x = (clamped as f64 * 2_147_483_647.) as i32;
+                bytes[..4].copy_from_slice(&x.to_le_bytes());
+            }
+            Format::Float32 => bytes[..4].copy_from_slice(&x.to_le_bytes()),
+            Format::Float64 => bytes[..8].copy_from_slice(&(x as f64).to_le_bytes()),
+        }
+    }
+}
+
+
+// - wav::Spec ----------------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Spec {
+    pub fs: u32,
+    pub num_channels: u16,
+    pub format: Format,
+}
+
+
+impl Spec {
+    pub fn new(fs: u32, num_channels: u16, format: Format) -> Spec {
+        Spec {
+            fs: fs,
+            num_channels: num_channels,
+            format: format,
+        }
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.num_channels as usize * self.format.bytes_per_sample()
+    }
+
+    fn parse(chunk: &[u8]) -> Result<Spec, EspError> {
+        if chunk.len() < FMT_SIZE {
+            return Err(idf::ESP_ERR_INVALID_SIZE.into());
+        }
+        let mut tag = read_u16(chunk, 0);
+        let num_channels = read_u16(chunk, 2);
+        let fs = read_u32(chunk, 4);
+        let bits = read_u16(chunk, 14);
+        if tag == FORMAT_EXTENSIBLE {
+            // the sub format guid starts with the format tag
+            if chunk.len() < 26 {
+                return Err(idf::ESP_ERR_INVALID_SIZE.into());
+            }
+            tag = read_u16(chunk, 24);
+        }
+        let format = match Format::from_header(tag, bits) {
+            Some(format) => format,
+            None => return Err(idf::ESP_ERR_NOT_SUPPORTED.into()),
+        };
+        if num_channels == 0 {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        Ok(Spec::new(fs, num_channels, format))
+    }
+
+    fn emit(&self) -> [u8; FMT_SIZE] {
+        let block_align = self.frame_size() as u16;
+        let mut chunk = [0; FMT_SIZE];
+        chunk[0..2].copy_from_slice(&self.format.tag().to_le_bytes());
+        chunk[2..4].copy_from_slice(&self.num_channels.to_le_bytes());
+        chunk[4..8].copy_from_slice(&self.fs.to_le_bytes());
+        chunk[8..12].copy_from_slice(&(self.fs * block_align as u32).to_le_bytes());
+        chunk[12..14].copy_from_slice(&block_align.to_le_bytes());
+        chunk[14..16].copy_from_slice(&(self.format.bytes_per_sample() as u16 * 8).to_le_bytes());
+        chunk
+    }
+}
+
+
+// - wav::SampleInfo ----------------------------------------------------------
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoopKind {
+    Forward,
+    PingPong,
+    Backward,
+}
+
+// Loop points in frames. `end` is exclusive, unlike the inclusive end
+// stored in the `smpl` chunk.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Loop {
+    pub start: u32,
+    pub end: u32,
+    pub kind: LoopKind,
+    pub play_count: u32, // 0 loops forever
+}
+
+// contents of the `smpl` chunk
+#[derive(Clone, PartialEq, Debug)]
+pub struct SampleInfo {
+    pub root: u8,       // midi note played back at the original pitch
+    pub fine_tune: f32, // cents above `root`
+    pub loops: Vec<Loop>,
+}
+
+
+impl SampleInfo {
+    fn parse(chunk: &[u8]) -> Result<SampleInfo, EspError> {
+        if chunk.len() < SMPL_HEADER_SIZE {
+            return Err(idf::ESP_ERR_INVALID_SIZE.into());
+        }
+        let root = read_u32(chunk, 12).min(127) as u8;
+        let fine_tune = read_u32(chunk, 16) as f32 * (100. / 4_294_967_296.);
+        let num_loops = read_u32(chunk, 28) as usize;
+        let available = (chunk.len() - SMPL_HEADER_SIZE) / SMPL_LOOP_SIZE;
+        let loops = (0..num_loops.min(available)).map(|index| {
+            let offset = SMPL_HEADER_SIZE + index * SMPL_LOOP_SIZE;
+            Loop {
+                start: read_u32(chunk, offset + 8),
+                end: read_u32(chunk, offset + 12).saturating_add(1),
+                kind: match read_u32(chunk, offset + 4) {
+                    1 => LoopKind::PingPong,
+                    2 => LoopKind::Backward,
+                    _ => LoopKind::Forward,
+                },
+                play_count: read_u32(chunk, offset + 20),
+            }
+        }).collect();
+        Ok(SampleInfo {
+            root: root,
+            fine_tune: fine_tune,
+            loops: loops,
+        })
+    }
+
+    fn emit(&self, fs: u32) -> Vec<u8> {
+        let mut chunk = vec![0; SMPL_HEADER_SIZE + self.loops.len() * SMPL_LOOP_SIZE];
+        let period = if fs > 0 { 1_000_000_000 / fs } else { 0 };
+        let fraction = (self.fine_tune as f64 / 100. * 4_294_967_296.) as u32;
+        write_u32(&mut chunk, 8, period);
+        write_u32(&mut chunk, 12, self.root as u32);
+        write_u32(&mut chunk, 16, fraction);
+        write_u32(&mut chunk, 28, self.loops.len() as u32);
+        for (index, l) in self.loops.iter().enumerate() {
+            let offset = SMPL_HEADER_SIZE + index * SMPL_LOOP_SIZE;
+            let kind = match l.kind {
+                LoopKind::Forward => 0,
+                LoopKind::PingPong => 1,
+                LoopKind::Backward => 2,
+            };
+            write_u32(&mut chunk, offset, index as u32);
+            write_u32(&mut chunk, offset + 4, kind);
+            write_u32(&mut chunk, offset + 8, l.start);
+            write_u32(&mut chunk, offset + 12, l.end.saturating_sub(1));
+            write_u32(&mut chunk, offset + 20, l.play_count);
+        }
+        chunk
+    }
+}
+
+
+// - wav::Wav -----------------------------------------------------------------
+
+// WAV file parsed in place from a byte slice, e.g. a file embedded in
+// flash with `include_bytes!` or loaded into PSRAM.
+
+pub struct Wav<'a> {
+    pub spec: Spec,
+    pub sample_info: Option<SampleInfo>,
+    data: &'a [u8],
+}
+
+
+impl<'a> Wav<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Wav<'a>, EspError> {
+        check_riff(bytes)?;
+
+        let mut spec = None;
+        let mut sample_info = None;
+        let mut data = None;
+        let mut offset = RIFF_HEADER_SIZE;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = read_u32(bytes, offset + 4) as usize;
+            let start = offset + 8;
+            let end = start.saturating_add(size).min(bytes.len());
+            let chunk = &bytes[start..end];
+            match id {
+                b"fmt " => spec = Some(Spec::parse(chunk)?),
+                b"smpl" => sample_info = Some(SampleInfo::parse(chunk)?),
+                b"data" => data = Some(chunk),
+                _ => (),
+            }
+            // chunks are padded to an even size
+            offset = end.saturating_add(size & 1);
+        }
+
+        let spec = match spec {
+            Some(spec) => spec,
+            None => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        };
+        let data = match data {
+            Some(data) => data,
+            None => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+        };
+        let frames = data.len() / spec.frame_size();
+
+        Ok(Wav {
+            spec: spec,
+            sample_info: sample_info,
+            data: &data[..frames * spec.frame_size()],
+        })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.data.len() / self.spec.frame_size()
+    }
+
+    // raw sample data
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn sample(&self, frame: usize, channel: usize) -> f32 {
+        let bytes_per_sample = self.spec.format.bytes_per_sample();
+        let offset = frame * self.spec.frame_size() + channel * bytes_per_sample;
+        self.spec.format.decode(&self.data[offset..offset + bytes_per_sample])
+    }
+
+    // Converts interleaved samples starting at `frame` into `output` and
+    // returns the number of frames read.
+    pub fn read<S: Pcm>(&self, frame: usize, output: &mut [S]) -> usize {
+        let num_channels = self.spec.num_channels as usize;
+        let bytes_per_sample = self.spec.format.bytes_per_sample();
+        let frames = self.frames().saturating_sub(frame).min(output.len() / num_channels);
+        let start = frame * self.spec.frame_size();
+        let bytes = &self.data[start..start + frames * self.spec.frame_size()];
+        for (sample, bytes) in output.iter_mut().zip(bytes.chunks(bytes_per_sample)) {
+            *sample = S::from_f32(self.spec.format.decode(bytes));
+        }
+        frames
+    }
+}
+
+
+// - wav::Read / wav::Write ---------------------------------------------------
+
+// Minimal byte stream traits standing in for `std::io` so files can be
+// streamed from and to flash, SD cards or the network.
+
+pub trait Read {
+    // returns the number of bytes read, 0 at the end of the stream
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, EspError>;
+}
+
+pub trait Write {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), EspError>;
+
+    // overwrite bytes written earlier, used to fill in chunk sizes
+    fn patch(&mut self, offset: usize, bytes: &[u8]) -> Result<(), EspError>;
+}
+
+impl<'a> Read for &'a [u8] {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, EspError> {
+        let count = buffer.len().min(self.len());
+        buffer[..count].copy_from_slice(&self[..count]);
+        *self = &self[count..];
+        Ok(count)
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), EspError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn patch(&mut self, offset: usize, bytes: &[u8]) -> Result<(), EspError> {
+        if offset + bytes.len() > self.len() {
+            return Err(idf::ESP_ERR_INVALID_ARG.into());
+        }
+        self[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+
+// - wav::Reader --------------------------------------------------------------
+
+// Streaming reader. Parses the header up to the start of the `data`
+// chunk, so a `smpl` chunk is only seen when it comes before the data.
+
+pub struct Reader<R> {
+    pub spec: Spec,
+    pub sample_info: Option<SampleInfo>,
+    reader: R,
+    remaining: usize, // bytes of sample data
+}
+
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut reader: R) -> Result<Reader<R>, EspError> {
+        let mut header = [0; RIFF_HEADER_SIZE];
+        read_exact(&mut reader, &mut header)?;
+        check_riff(&header)?;
+
+        // bytes left in the riff chunk, a size of 0 is left by writers
+        // that never went back to fill it in
+        let mut remaining = match read_u32(&header, 4) as usize {
+            0 => usize::MAX,
+            size => size.saturating_sub(4),
+        };
+
+        let mut spec = None;
+        let mut sample_info = None;
+        loop {
+            let mut chunk_header = [0; 8];
+            if read_exact(&mut reader, &mut chunk_header).is_err() {
+                // reached the end without a data chunk
+                return Err(idf::ESP_ERR_NOT_FOUND.into());
+            }
+            let size = read_u32(&chunk_header, 4) as usize;
+            let padded = size.saturating_add(size & 1);
+            remaining = remaining.saturating_sub(8);
+            match &chunk_header[0..4] {
+                b"fmt " | b"smpl" => {
+                    if size > remaining.min(MAX_CHUNK_SIZE) {
+                        return Err(idf::ESP_ERR_INVALID_SIZE.into());
+                    }
+                    remaining = remaining.saturating_sub(padded);
+                    let mut chunk = vec![0; size];
+                    read_exact(&mut reader, &mut chunk)?;
+                    skip(&mut reader, size & 1)?;
+                    if &chunk_header[0..4] == b"fmt " {
+                        spec = Some(Spec::parse(&chunk)?);
+                    } else {
+                        sample_info = Some(SampleInfo::parse(&chunk)?);
+                    }
+                }
+                b"data" => {
+                    let spec = match spec {
+                        Some(spec) => spec,
+                        None => return Err(idf::ESP_ERR_NOT_FOUND.into()),
+                    };
+                    return Ok(Reader {
+                        spec: spec,
+                        sample_info: sample_info,
+                        reader: reader,
+                        remaining: size - size % spec.frame_size(),
+                    });
+                }
+                _ => {
+                    remaining = remaining.saturating_sub(padded);
+                    skip(&mut reader, padded)?;
+                }
+            }
+        }
+    }
+
+    pub fn frames_remaining(&self) -> usize {
+        self.remaining / self.spec.frame_size()
+    }
+
+    // Converts the next interleaved frames into `output` and returns the
+    // number of frames read, 0 once the data chunk is exhausted.
+    pub fn read<S: Pcm>(&mut self, output: &mut [S]) -> Result<usize, EspError> {
+        let num_channels = self.spec.num_channels as usize;
+        let bytes_per_sample = self.spec.format.bytes_per_sample();
+        let frames = self.frames_remaining().min(output.len() / num_channels);
+        let samples = frames * num_channels;
+        let block = (SCRATCH_SIZE / bytes_per_sample) * bytes_per_sample;
+
+        let mut scratch = [0; SCRATCH_SIZE];
+        let mut position = 0;
+        while position < samples {
+            let count = ((samples - position) * bytes_per_sample).min(block);
+            read_exact(&mut self.reader, &mut scratch[..count])?;
+            for bytes in scratch[..count].chunks(bytes_per_sample) {
+                output[position] = S::from_f32(self.spec.format.decode(bytes));
+                position += 1;
+            }
+        }
+        self.remaining -= frames * self.spec.frame_size();
+        Ok(frames)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+
+// - wav::Writer --------------------------------------------------------------
+
+// Streaming writer. Chunk sizes are filled in by `finish`, which also
+// appends the optional `smpl` chunk after the data.
+
+pub struct Writer<W: Write> {
+    pub spec: Spec,
+    writer: W,
+    data_size: usize,
+}
+
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut writer: W, spec: Spec) -> Result<Writer<W>, EspError> {
+        writer.write(b"RIFF\0\0\0\0WAVE")?;
+        writer.write(b"fmt ")?;
+        writer.write(&(FMT_SIZE as u32).to_le_bytes())?;
+        writer.write(&spec.emit())?;
+        writer.write(b"data\0\0\0\0")?;
+        Ok(Writer {
+            spec: spec,
+            writer: writer,
+            data_size: 0,
+        })
+    }
+
+    pub fn frames_written(&self) -> usize {
+        self.data_size / self.spec.frame_size()
+    }
+
+    // write interleaved samples
+    pub fn write<S: Pcm>(&mut self, input: &[S]) -> Result<(), EspError> {
+        let bytes_per_sample = self.spec.format.bytes_per_sample();
+        let samples_per_block = SCRATCH_SIZE / bytes_per_sample;
+        let mut scratch = [0; SCRATCH_SIZE];
+        for block in input.chunks(samples_per_block) {
+            for (sample, bytes) in block.iter().zip(scratch.chunks_mut(bytes_per_sample)) {
+                self.spec.format.encode(sample.to_f32(), bytes);
+            }
+            self.writer.write(&scratch[..block.len() * bytes_per_sample])?;
+        }
+        self.data_size += input.len() * bytes_per_sample;
+        Ok(())
+    }
+
+    pub fn finish(mut self, sample_info: Option<&SampleInfo>) -> Result<W, EspError> {
+        let mut riff_size = 4 + 8 + FMT_SIZE + 8 + self.data_size;
+        if self.data_size & 1 == 1 {
+            self.writer.write(&[0])?;
+            riff_size += 1;
+        }
+        if let Some(sample_info) = sample_info {
+            let chunk = sample_info.emit(self.spec.fs);
+            self.writer.write(b"smpl")?;
+            self.writer.write(&(chunk.len() as u32).to_le_bytes())?;
+            self.writer.write(&chunk)?;
+            riff_size += 8 + chunk.len();
+        }
+        self.writer.patch(4, &(riff_size as u32).to_le_bytes())?;
+        self.writer.patch(DATA_SIZE_OFFSET, &(self.data_size as u32).to_le_bytes())?;
+        Ok(self.writer)
+    }
+}
+
+
+// - helpers ------------------------------------------------------------------
+
+#[inline(always)]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline(always)]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[inline(always)]
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn check_riff(bytes: &[u8]) -> Result<(), EspError> {
+    if bytes.len() < RIFF_HEADER_SIZE || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(idf::ESP_ERR_INVALID_ARG.into());
+    }
+    Ok(())
+}
+
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), EspError> {
+    let mut position = 0;
+    while position < buffer.len() {
+        let count = reader.read(&mut buffer[position..])?;
+        if count == 0 {
+            return Err(idf::ESP_ERR_INVALID_SIZE.into());
+        }
+        position += count;
+    }
+    Ok(())
+}
+
+fn skip<R: Read>(reader: &mut R, mut count: usize) -> Result<(), EspError> {
+    let mut scratch = [0; SCRATCH_SIZE];
+    while count > 0 {
+        let n = count.min(SCRATCH_SIZE);
+        read_exact(reader, &mut scratch[..n])?;
+        count -= n;
+    }
+    Ok(())
+}
+
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use esp_idf::bindings as idf;
+    use esp_idf::EspError;
+
+    use super::*;
+
+    const FORMATS: [Format; 6] = [
+        Format::Pcm8, Format::Pcm16, Format::Pcm24, Format::Pcm32, Format::Float32, Format::Float64,
+    ];
+
+    fn test_signal(frames: usize, num_channels: usize) -> Vec<f32> {
+        (0..frames * num_channels).map(|index| {
+            let frame = index / num_channels;
+            let channel = index % num_channels;
+            crate::math::sinf(frame as f32 * 0.05 * (channel + 1) as f32) * 0.9
+        }).collect()
+    }
+
+    fn tolerance(format: Format) -> f32 {
+        match format {
+            Format::Pcm8 => 1. / 64.,
+            Format::Pcm16 => 1. / 16_000.,
+            _ => 1e-6,
+        }
+    }
+
+    fn encode(spec: Spec, input: &[f32], sample_info: Option<&SampleInfo>) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), spec).unwrap();
+        writer.write(input).unwrap();
+        writer.finish(sample_info).unwrap()
+    }
+
+    // returns a few bytes at a time to exercise the streaming paths
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, EspError> {
+            let count = buffer.len().min(self.step).min(self.bytes.len());
+            buffer[..count].copy_from_slice(&self.bytes[..count]);
+            self.bytes = &self.bytes[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for &format in FORMATS.iter() {
+            for &num_channels in [1usize, 2, 6].iter() {
+                let input = test_signal(333, num_channels);
+                let spec = Spec::new(44_100, num_channels as u16, format);
+                let bytes = encode(spec, &input, None);
+
+                let wav = Wav::parse(&bytes).unwrap();
+                assert_eq!(wav.spec, spec);
+                assert_eq!(wav.frames(), 333);
+                assert_eq!(wav.sample_info, None);
+                let mut output = vec![0f32; input.len()];
+                assert_eq!(wav.read(0, &mut output), 333);
+                for (a, b) in input.iter().zip(output.iter()) {
+                    assert!((a - b).abs() <= tolerance(format), "{:?} {} {} {}", format, num_channels, a, b);
+                }
+                assert_eq!(wav.sample(10, num_channels - 1), output[10 * num_channels + num_channels - 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn header_layout() {
+        let input = test_signal(3, 1);
+        let bytes = encode(Spec::new(48_000, 1, Format::Pcm16), &input, None);
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), 42);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]), 6);
+
+        // odd sized data is padded
+        let bytes = encode(Spec::new(48_000, 1, Format::Pcm8), &input, None);
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), 40);
+    }
+
+    #[test]
+    fn sample_info() {
+        let info = SampleInfo {
+            root: 57,
+            fine_tune: 25.,
+            loops: vec![
+                Loop { start: 10, end: 200, kind: LoopKind::Forward, play_count: 0 },
+                Loop { start: 50, end: 60, kind: LoopKind::PingPong, play_count: 3 },
+            ],
+        };
+        let input = test_signal(301, 2);
+        let bytes = encode(Spec::new(44_100, 2, Format::Pcm24), &input, Some(&info));
+        let wav = Wav::parse(&bytes).unwrap();
+        let parsed = wav.sample_info.as_ref().unwrap();
+        assert_eq!(parsed.root, 57);
+        assert!((parsed.fine_tune - 25.).abs() < 1e-3);
+        assert_eq!(parsed.loops, info.loops);
+        assert_eq!(wav.frames(), 301);
+    }
+
+    #[test]
+    fn streaming() {
+        let input = test_signal(1000, 3);
+        let spec = Spec::new(32_000, 3, Format::Pcm24);
+        let bytes = encode(spec, &input, None);
+
+        let mut reader = Reader::new(Trickle { bytes: &bytes, step: 7 }).unwrap();
+        assert_eq!(reader.spec, spec);
+        assert_eq!(reader.frames_remaining(), 1000);
+        let mut output = Vec::new();
+        let mut block = [0f32; 3 * 37];
+        loop {
+            let frames = reader.read(&mut block).unwrap();
+            if frames == 0 {
+                break;
+            }
+            output.extend_from_slice(&block[..frames * 3]);
+        }
+        assert_eq!(output.len(), input.len());
+        for (a, b) in input.iter().zip(output.iter()) {
+            assert!((a - b).abs() <= 1e-6);
+        }
+
+        // into i16 storage, e.g. a `sample::Sample`
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        let mut pcm = vec![0i16; input.len()];
+        assert_eq!(reader.read(&mut pcm).unwrap(), 1000);
+        assert_eq!(pcm[4], i16::from_f32(input[4]));
+    }
+
+    #[test]
+    fn foreign_chunks() {
+        // extensible fmt, a LIST chunk with an odd size and a smpl chunk
+        // ahead of the data, as written by many editors
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        let mut fmt = [0u8; 40];
+        fmt[0..2].copy_from_slice(&FORMAT_EXTENSIBLE.to_le_bytes());
+        fmt[2..4].copy_from_slice(&2u16.to_le_bytes());
+        fmt[4..8].copy_from_slice(&48_000u32.to_le_bytes());
+        fmt[12..14].copy_from_slice(&8u16.to_le_bytes());
+        fmt[14..16].copy_from_slice(&32u16.to_le_bytes());
+        fmt[16..18].copy_from_slice(&22u16.to_le_bytes());
+        fmt[24..26].copy_from_slice(&FORMAT_FLOAT.to_le_bytes());
+        bytes.extend_from_slice(&fmt);
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&5u32.to_le_bytes());
+        bytes.extend_from_slice(b"INFO!\0");
+        let info = SampleInfo { root: 60, fine_tune: 0., loops: vec![] };
+        let smpl = info.emit(48_000);
+        bytes.extend_from_slice(b"smpl");
+        bytes.extend_from_slice(&(smpl.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&smpl);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        for x in [0.5f32, -0.5, 0.25, -0.25].iter() {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+
+        let wav = Wav::parse(&bytes).unwrap();
+        assert_eq!(wav.spec, Spec::new(48_000, 2, Format::Float32));
+        assert_eq!(wav.frames(), 2);
+        assert_eq!(wav.sample(1, 1), -0.25);
+        assert_eq!(wav.sample_info.as_ref().map(|info| info.root), Some(60));
+
+        let mut reader = Reader::new(Trickle { bytes: &bytes, step: 3 }).unwrap();
+        assert_eq!(reader.sample_info, Some(info));
+        let mut output = [0f32; 4];
+        assert_eq!(reader.read(&mut output).unwrap(), 2);
+        assert_eq!(output, [0.5, -0.5, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn errors() {
+        let code = |result: Result<Wav, EspError>| result.err().map(|error| error.0 as u32);
+        assert_eq!(code(Wav::parse(b"RIFX\0\0\0\0WAVE")), Some(idf::ESP_ERR_INVALID_ARG));
+        assert_eq!(code(Wav::parse(b"RIFF")), Some(idf::ESP_ERR_INVALID_ARG));
+        assert_eq!(code(Wav::parse(b"RIFF\0\0\0\0WAVE")), Some(idf::ESP_ERR_NOT_FOUND));
+
+        // 12 bit pcm
+        let mut bytes = encode(Spec::new(8_000, 1, Format::Pcm16), &[0.; 4], None);
+        bytes[34] = 12;
+        assert_eq!(code(Wav::parse(&bytes)), Some(idf::ESP_ERR_NOT_SUPPORTED));
+
+        // truncated data keeps the whole frames that are there
+        let bytes = encode(Spec::new(8_000, 2, Format::Pcm16), &[0.; 8], None);
+        let wav = Wav::parse(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(wav.frames(), 3);
+
+        // streaming a truncated file fails on read
+        let mut reader = Reader::new(&bytes[..bytes.len() - 3]).unwrap();
+        let mut output = [0f32; 8];
+        assert!(reader.read(&mut output).is_err());
+
+        // header chunk sizes are checked before anything is allocated
+        let code = |result: Result<Reader<&[u8]>, EspError>| result.err().map(|error| error.0 as u32);
+        let mut bytes = encode(Spec::new(8_000, 1, Format::Pcm16), &[0.; 4], None);
+        write_u32(&mut bytes, 16, 0xffff_fff0);
+        assert_eq!(code(Reader::new(&bytes[..])), Some(idf::ESP_ERR_INVALID_SIZE));
+        write_u32(&mut bytes, 16, 1000);
+        assert_eq!(code(Reader::new(&bytes[..])), Some(idf::ESP_ERR_INVALID_SIZE));
+    }
+}
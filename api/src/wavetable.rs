@@ -244,6 +244,20 @@ pub static SIN: [f32; LENGTH] = [
     -0.036807224, -0.024541229, -0.012271538
 ];
 
+
+// - lookup -------------------------------------------------------------------
+
+// linearly interpolated lookup for a phase in [0, 1)
+#[inline(always)]
+pub fn lookup(wt: &Wavetable, phase: f32) -> f32 {
+    let index = phase * LENGTH as f32;
+    let int_part = index as usize;
+    let frac_part = index - int_part as f32;
+    let x0 = int_part & (LENGTH - 1);
+    let x1 = (x0 + 1) & (LENGTH - 1);
+    wt[x0] + ((wt[x1] - wt[x0]) * frac_part)
+}
+
 /*
 pub fn generate_sin() {
     let length = 512;